@@ -8,15 +8,19 @@ use cosmwasm_std::{
 };
 use cw20::{Cw20ReceiveMsg};
 use margined_perp::margined_engine::{ExecuteMsg, InstantiateMsg, QueryMsg, Cw20HookMsg};
+use margined_perp::signed_decimal::SignedDecimal;
 
 use crate::error::ContractError;
 use crate::{
     handle::{
         update_config, increase_position, decrease_position, reverse_position,
-        open_position, close_position, finalize_close_position,
+        open_position, close_position, finalize_close_position, settle_position,
+        liquidate, finalize_liquidation, finalize_partial_liquidation,
+        add_margin, remove_margin, pay_funding, finalize_pay_funding, unpause,
     },
     query::{
         query_config, query_position, query_trader_balance_with_funding_payment,
+        query_margin_ratio, query_bad_debt,
     },
     state::{Config, read_config, store_config, store_vamm},
 };
@@ -25,6 +29,9 @@ pub const SWAP_INCREASE_REPLY_ID: u64 = 1;
 pub const SWAP_DECREASE_REPLY_ID: u64 = 2;
 pub const SWAP_REVERSE_REPLY_ID: u64 = 3;
 pub const CLOSE_POSITION_REPLY_ID: u64 = 4;
+pub const LIQUIDATION_REPLY_ID: u64 = 5;
+pub const PARTIAL_LIQUIDATION_REPLY_ID: u64 = 6;
+pub const PAY_FUNDING_REPLY_ID: u64 = 7;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -35,15 +42,22 @@ pub fn instantiate(
 ) -> Result<Response, ContractError> {
     let decimals = Uint128::from(10u128.pow(msg.decimals as u32));
     let eligible_collateral = deps.api.addr_validate(&msg.eligible_collateral)?;
+    let insurance_fund = deps.api.addr_validate(&msg.insurance_fund)?;
+    let fee_pool = deps.api.addr_validate(&msg.fee_pool)?;
 
     // config parameters
     let config = Config {
         owner: info.sender.clone(),
-        eligible_collateral: eligible_collateral,
-        decimals: decimals,
+        eligible_collateral,
+        insurance_fund,
+        fee_pool,
+        decimals,
         initial_margin_ratio: msg.initial_margin_ratio,
         maintenance_margin_ratio: msg.maintenance_margin_ratio,
         liquidation_fee: msg.liquidation_fee,
+        partial_liquidation_ratio: msg.partial_liquidation_ratio,
+        spread_limit_ratio: msg.spread_limit_ratio,
+        bad_debt_mode: msg.bad_debt_mode,
     };
     
     store_config(deps.storage, &config)?;
@@ -70,11 +84,17 @@ pub fn execute(
         ),
         ExecuteMsg::UpdateConfig {
             owner,
+            partial_liquidation_ratio,
+            spread_limit_ratio,
+            bad_debt_mode,
         } => {
             update_config(
                 deps,
                 info.clone(),
                 owner,
+                partial_liquidation_ratio,
+                spread_limit_ratio,
+                bad_debt_mode,
             )
         },
         ExecuteMsg::OpenPosition {
@@ -82,6 +102,7 @@ pub fn execute(
             side,
             quote_asset_amount,
             leverage,
+            base_asset_amount_limit,
          } => {
              let trader = info.sender.clone();
          open_position(
@@ -93,9 +114,12 @@ pub fn execute(
             side,
             quote_asset_amount,
             leverage,
+            base_asset_amount_limit,
+            true,
         )},
         ExecuteMsg::ClosePosition {
             vamm,
+            quote_asset_amount_limit,
          } => {
              let trader = info.sender.clone();
          close_position(
@@ -104,8 +128,81 @@ pub fn execute(
             info.clone(),
             vamm,
             trader.to_string(),
+            quote_asset_amount_limit,
             CLOSE_POSITION_REPLY_ID,
         )},
+        ExecuteMsg::Liquidate {
+            vamm,
+            trader,
+        } => {
+            liquidate(
+                deps,
+                env,
+                info,
+                vamm,
+                trader,
+            )
+        },
+        ExecuteMsg::AddMargin {
+            vamm,
+            amount,
+        } => {
+            let trader = info.sender.clone();
+            add_margin(
+                deps,
+                env,
+                info,
+                vamm,
+                trader.to_string(),
+                amount,
+                true,
+            )
+        },
+        ExecuteMsg::RemoveMargin {
+            vamm,
+            amount,
+        } => {
+            let trader = info.sender.clone();
+            remove_margin(
+                deps,
+                env,
+                info,
+                vamm,
+                trader.to_string(),
+                amount,
+            )
+        },
+        ExecuteMsg::PayFunding {
+            vamm,
+        } => {
+            pay_funding(
+                deps,
+                env,
+                info,
+                vamm,
+            )
+        },
+        ExecuteMsg::Unpause {
+            vamm,
+        } => {
+            unpause(
+                deps,
+                info,
+                vamm,
+            )
+        },
+        ExecuteMsg::SettlePosition {
+            vamm,
+        } => {
+            let trader = info.sender.clone();
+            settle_position(
+                deps,
+                env,
+                info.clone(),
+                vamm,
+                trader.to_string(),
+            )
+        },
     }
 }
 
@@ -126,6 +223,7 @@ pub fn receive_cw20(
             vamm,
             side,
             leverage,
+            base_asset_amount_limit,
         }) => open_position(
             deps,
             env,
@@ -133,8 +231,21 @@ pub fn receive_cw20(
             vamm,
             cw20_msg.sender,
             side,
-            cw20_msg.amount, // not needed, we should take from deposited amount or validate
+            cw20_msg.amount,
             leverage,
+            base_asset_amount_limit,
+            false, // the margin has already been sent with the hook
+        ),
+        Ok(Cw20HookMsg::AddMargin {
+            vamm,
+        }) => add_margin(
+            deps,
+            env,
+            info,
+            vamm,
+            cw20_msg.sender,
+            cw20_msg.amount,
+            false, // the funds have already been sent with the hook
         ),
         Err(_) => Err(StdError::generic_err("invalid cw20 hook message")),
     }
@@ -151,6 +262,14 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
         QueryMsg::TraderBalance {
             trader,
         } => to_binary(&query_trader_balance_with_funding_payment(deps, trader)?),
+        QueryMsg::MarginRatio {
+            vamm,
+            trader,
+            calc,
+        } => to_binary(&query_margin_ratio(deps, vamm, trader, calc)?),
+        QueryMsg::BadDebt {
+            vamm,
+        } => to_binary(&query_bad_debt(deps, vamm)?),
     }
 }
 
@@ -199,6 +318,36 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
                     )?;
                     Ok(response)
                 },
+                LIQUIDATION_REPLY_ID => {
+                    let (input, output) = parse_swap(response);
+                    let response = finalize_liquidation(
+                        deps,
+                        env,
+                        input,
+                        output,
+                    )?;
+                    Ok(response)
+                },
+                PARTIAL_LIQUIDATION_REPLY_ID => {
+                    let (input, output) = parse_swap(response);
+                    let response = finalize_partial_liquidation(
+                        deps,
+                        env,
+                        input,
+                        output,
+                    )?;
+                    Ok(response)
+                },
+                PAY_FUNDING_REPLY_ID => {
+                    let (vamm, premium_fraction) = parse_funding(response);
+                    let response = finalize_pay_funding(
+                        deps,
+                        env,
+                        vamm,
+                        premium_fraction,
+                    )?;
+                    Ok(response)
+                },
                 _ => Err(StdError::generic_err(format!(
                     "reply (id {:?}) invalid",
                     msg.id
@@ -235,6 +384,30 @@ fn parse_swap(
         .value;
     let output: Uint128 = Uint128::from_str(output_str).unwrap();
 
-    return (input, output)
+    (input, output)
 }
 
+fn parse_funding(
+    response: SubMsgExecutionResponse,
+) -> (String, SignedDecimal) {
+    // Find the vamm and the premium fraction it settled
+    let wasm = response.events.iter().find(|&e| e.ty == "wasm");
+    let wasm = wasm.unwrap();
+    let vamm = wasm
+        .attributes
+        .iter()
+        .find(|&attr| attr.key == "_contract_addr")
+        .unwrap()
+        .value
+        .clone();
+
+    let premium_fraction_str = &wasm
+        .attributes
+        .iter()
+        .find(|&attr| attr.key == "premium_fraction")
+        .unwrap()
+        .value;
+    let premium_fraction: SignedDecimal = SignedDecimal::from_str(premium_fraction_str).unwrap();
+
+    (vamm, premium_fraction)
+}
@@ -1,18 +1,35 @@
+use std::convert::TryFrom;
+
 use cosmwasm_std::{
-    Addr, CosmosMsg, DepsMut, Env, MessageInfo, Response,
+    Addr, CosmosMsg, Deps, DepsMut, Env, Event, MessageInfo, Response,
     ReplyOn, StdError, StdResult, SubMsg, to_binary, Uint128,
     WasmMsg, Storage,
 };
 use cw20::{Cw20ExecuteMsg};
 
 use margined_perp::margined_vamm::{Direction, ExecuteMsg};
-use margined_perp::margined_engine::Side;
+use margined_perp::margined_engine::{BadDebtMode, PNLCalc, Side};
+use margined_perp::margined_insurance_fund::ExecuteMsg as InsuranceFundExecuteMsg;
+use margined_perp::signed_decimal::SignedDecimal;
 use crate::{
-    contract::{SWAP_INCREASE_REPLY_ID, SWAP_DECREASE_REPLY_ID, SWAP_REVERSE_REPLY_ID},
+    contract::{
+        SWAP_INCREASE_REPLY_ID, SWAP_DECREASE_REPLY_ID, SWAP_REVERSE_REPLY_ID,
+        LIQUIDATION_REPLY_ID, PARTIAL_LIQUIDATION_REPLY_ID, PAY_FUNDING_REPLY_ID,
+    },
+    querier::{
+        query_insurance_fund_balance, query_vamm_calc_fee, query_vamm_config, query_vamm_liquidity_history_length,
+        query_vamm_output_price, query_vamm_spot_price, query_vamm_state,
+        query_vamm_twap, query_vamm_underlying_price, query_token_balance,
+    },
     state::{
         Config, read_config, store_config,
-        Position, read_position, store_position,
-        store_tmp_position, read_tmp_position, remove_tmp_position,
+        Position, read_position, store_position, remove_position,
+        TmpSwapInfo, store_tmp_swap, read_tmp_swap, remove_tmp_swap,
+        TmpReverseInfo, store_tmp_reverse, read_tmp_reverse, remove_tmp_reverse,
+        store_tmp_liquidator, read_tmp_liquidator, remove_tmp_liquidator,
+        store_tmp_exchanged, read_tmp_exchanged, remove_tmp_exchanged,
+        read_open_interest_notional, store_open_interest_notional,
+        read_bad_debt, store_bad_debt,
         VammList, read_vamm,
     },
 };
@@ -20,25 +37,72 @@ use crate::{
 pub fn update_config(
     deps: DepsMut,
     info: MessageInfo,
-    owner: String,
+    owner: Option<String>,
+    partial_liquidation_ratio: Option<Uint128>,
+    spread_limit_ratio: Option<Uint128>,
+    bad_debt_mode: Option<BadDebtMode>,
 ) -> StdResult<Response> {
-    let config = read_config(deps.storage)?;
+    let mut config = read_config(deps.storage)?;
     if info.sender != config.owner {
         return Err(StdError::generic_err("unauthorized"));
     }
 
-    let new_config = Config {
-        owner: deps.api.addr_validate(&owner).unwrap(),
-        ..config
-    };
+    // change owner of the engine
+    if let Some(owner) = owner {
+        config.owner = deps.api.addr_validate(&owner)?;
+    }
 
-    store_config(deps.storage, &new_config)?;
+    // change the partial liquidation ratio, zero always liquidates in full
+    if let Some(partial_liquidation_ratio) = partial_liquidation_ratio {
+        if partial_liquidation_ratio > config.decimals {
+            return Err(StdError::generic_err("partial liquidation ratio must be at most one"));
+        }
+        config.partial_liquidation_ratio = partial_liquidation_ratio;
+    }
+
+    // change the spread limit ratio, zero always uses the spot price
+    if let Some(spread_limit_ratio) = spread_limit_ratio {
+        config.spread_limit_ratio = spread_limit_ratio;
+    }
+
+    // change what happens to bad debt the insurance fund can't cover
+    if let Some(bad_debt_mode) = bad_debt_mode {
+        config.bad_debt_mode = bad_debt_mode;
+    }
+
+    store_config(deps.storage, &config)?;
 
     Ok(Response::default())
 }
 
+// Unpauses a vamm that was paused because of bad debt the insurance fund
+// couldn't cover
+pub fn unpause(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+) -> StdResult<Response> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    let mut bad_debt = read_bad_debt(deps.storage, &vamm)?;
+    bad_debt.paused = false;
+    store_bad_debt(deps.storage, &vamm, &bad_debt)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "unpause"),
+            ("vamm", vamm.as_str()),
+        ])
+    )
+}
 
 // Opens a position
+#[allow(clippy::too_many_arguments)]
 pub fn open_position(
     deps: DepsMut,
     env: Env,
@@ -48,6 +112,8 @@ pub fn open_position(
     side: Side,
     quote_asset_amount: Uint128,
     leverage: Uint128,
+    base_asset_amount_limit: Option<Uint128>,
+    transfer: bool,
 ) -> StdResult<Response> {
     let config: Config = read_config(deps.storage)?;
     
@@ -65,41 +131,63 @@ pub fn open_position(
     let vamm = deps.api.addr_validate(&vamm)?;
     let trader = deps.api.addr_validate(&trader)?;
 
+    // positions can still be closed while the vamm is paused
+    if read_bad_debt(deps.storage, &vamm)?.paused {
+        return Err(StdError::generic_err("vAMM is paused"));
+    }
+
     // calc the input amount wrt to leverage and decimals
-    let open_notional = quote_asset_amount
-                .checked_mul(leverage)?
-                .checked_div(config.decimals)?;
+    let open_notional = multiply_by_ratio(
+        quote_asset_amount,
+        leverage,
+        config.decimals,
+    )?;
 
     // read the position for the trader from vamm
-    let current_position = read_position(deps.storage, &vamm, &trader)?;
-    let mut position = Position::default();
-
-    // so if the position returned is None then its new
-    if current_position.is_none() {
-        let direction: Direction = side_to_direction(side.clone());
+    let mut position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) => position,
+        // so if the position returned is None then its new
+        None => Position {
+            vamm: vamm.clone(),
+            trader: trader.clone(),
+            timestamp: env.block.time,
+            ..Position::default()
+        },
+    };
 
-        // update the default position
-        position.vamm = vamm.clone();
-        position.trader = trader.clone();
-        position.direction = direction.clone();
-        position.timestamp = env.block.time;
+    // the funding owed since the position was last touched is settled first
+    apply_funding_payment(deps.as_ref(), &mut position)?;
 
-    } else {
-        position = current_position.unwrap();
+    // an existing position has to be above the maintenance margin to trade,
+    // otherwise it can only be liquidated
+    if !position.size.is_zero() {
+        let margin_ratio = get_margin_ratio(deps.as_ref(), &position)?;
+        if margin_ratio < SignedDecimal::from(config.maintenance_margin_ratio) {
+            return Err(StdError::generic_err("position is undercollateralized"));
+        }
     }
-    
-    let mut is_increase: bool = true;
-    if !(position.direction == Direction::AddToAmm && side.clone() == Side::BUY) &&
-            !(position.direction == Direction::RemoveFromAmm && side.clone() == Side::SELL) {
-        is_increase = false;
+
+    // a position with no size is new, otherwise it increases if the trade
+    // is on the same side as the position
+    // a new position is opened under the latest liquidity of the vamm
+    if position.size.is_zero() {
+        position.liquidity_history_index = latest_liquidity_history_index(
+            deps.as_ref(),
+            &vamm,
+        )?;
     }
 
+    let is_increase: bool = position.size.is_zero() ||
+            (position.size.is_positive() && side == Side::BUY) ||
+            (position.size.is_negative() && side == Side::SELL);
+
     if is_increase {
         // then we are opening a new position or adding to an existing
         let swap_msg = swap_input(
             &vamm,
             side.clone(),
             open_notional,
+            base_asset_amount_limit,
             SWAP_INCREASE_REPLY_ID
         ).unwrap();
 
@@ -107,43 +195,63 @@ pub fn open_position(
         position.margin = position.margin.checked_add(quote_asset_amount)?;
         position.notional = position.notional.checked_add(open_notional)?;
 
-        let transfer_msg = execute_transfer_from(
-            deps.storage,
-            &trader.clone(),
-            &env.contract.address,
-            position.margin,
-        ).unwrap();
+        if transfer {
+            let transfer_msg = execute_transfer_from(
+                deps.storage,
+                &trader.clone(),
+                &env.contract.address,
+                quote_asset_amount,
+            ).unwrap();
+            response = response.add_submessage(transfer_msg);
+        }
 
         // Add the submessage to the response
         response = response
-            .add_submessage(transfer_msg)
+            .add_submessages(transfer_fees(deps.as_ref(), &trader, &vamm, open_notional)?)
             .add_submessage(swap_msg);
 
     } else {
-        // TODO make this a function maybe called, open_reverse_position
-        // if old position is greater then we don't need to reverse just reduce the position
-        if position.notional > open_notional {
+        // what the position would close for now, if it is worth more than
+        // the trade it is only reduced, otherwise it is closed and reversed
+        let position_notional = query_vamm_output_price(
+            &deps.as_ref(),
+            vamm.to_string(),
+            close_direction(&position.size),
+            position.size.abs(),
+        )?;
+
+        if position_notional > open_notional {
             // then we are opening a new position or adding to an existing
             let msg = swap_input(
                 &vamm,
                 side.clone(),
                 open_notional,
+                base_asset_amount_limit,
                 SWAP_DECREASE_REPLY_ID
             ).unwrap();
 
-            position.notional = position.notional.checked_sub(open_notional)?;
+            // reducing the position takes no margin, so anything sent with
+            // the trade is returned
+            if !transfer {
+                response = response.add_submessage(
+                    execute_transfer(&config, &trader, quote_asset_amount)?
+                );
+            }
 
             // Add the submessage to the response
-            response = response.add_submessage(msg);
-        } else {            
-            let amount = position.size;
-
+            response = response
+                .add_submessages(transfer_fees(deps.as_ref(), &trader, &vamm, open_notional)?)
+                .add_submessage(msg);
+        } else {
+            // the position is closed in full, the slippage limit applies to
+            // the new position that is opened with the rest of the trade
             let swap_msg = WasmMsg::Execute {
                 contract_addr: vamm.to_string(),
                 funds: vec![],
                 msg: to_binary(&ExecuteMsg::SwapOutput {
-                    direction: position.direction.clone(),
-                    base_asset_amount: amount,
+                    direction: close_direction(&position.size),
+                    base_asset_amount: position.size.abs(),
+                    quote_asset_amount_limit: None,
                 })?,
             };
 
@@ -154,12 +262,24 @@ pub fn open_position(
                 reply_on: ReplyOn::Always,
             };
 
+            // the fees are charged on the whole of the trade here, the rest
+            // of it is opened once the position has been closed
+            store_tmp_reverse(deps.storage, &TmpReverseInfo {
+                quote_asset_amount,
+                open_notional,
+                leverage,
+                base_asset_amount_limit,
+                transfer,
+            })?;
+
             // Add the submessage to the response
-            response = response.add_submessage(msg);
+            response = response
+                .add_submessages(transfer_fees(deps.as_ref(), &trader, &vamm, open_notional)?)
+                .add_submessage(msg);
         }
     }
 
-    store_tmp_position(deps.storage, &position)?;
+    store_tmp_swap(deps.storage, &TmpSwapInfo { position, side })?;
 
     Ok(
         response.add_attributes(vec![
@@ -175,6 +295,7 @@ pub fn close_position(
     _info: MessageInfo,
     vamm: String,
     trader: String,
+    quote_asset_amount_limit: Option<Uint128>,
     id: u64,
 ) -> StdResult<Response> {
     // validate address inputs
@@ -182,28 +303,31 @@ pub fn close_position(
     let trader = deps.api.addr_validate(&trader)?;
 
     // read the position for the trader from vamm
-    let position = read_position(deps.storage, &vamm, &trader)?.unwrap();
-
-    let direction: Direction = switch_direction(position.direction.clone());
-    let amount = position.size;
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => position,
+        _ => return Err(StdError::generic_err("no position to close")),
+    };
 
     let swap_msg = WasmMsg::Execute {
         contract_addr: vamm.to_string(),
         funds: vec![],
         msg: to_binary(&ExecuteMsg::SwapOutput {
-            direction: direction,
-            base_asset_amount: amount,
+            direction: close_direction(&position.size),
+            base_asset_amount: position.size.abs(),
+            quote_asset_amount_limit,
         })?,
     };
 
     let msg = SubMsg {
         msg: CosmosMsg::Wasm(swap_msg),
         gas_limit: None, // probably should set a limit in the config
-        id: id,
+        id,
         reply_on: ReplyOn::Always,
     };
 
-    store_tmp_position(deps.storage, &position)?;
+    // closing a long is a sell and closing a short is a buy
+    let side = if position.size.is_negative() { Side::BUY } else { Side::SELL };
+    store_tmp_swap(deps.storage, &TmpSwapInfo { position, side })?;
 
     Ok(Response::new()
         .add_attributes(vec![("action", "close_position")])
@@ -211,155 +335,1061 @@ pub fn close_position(
     )
 }
 
-// Closes position returning funds after successful execution of the swap out
-pub fn finalize_close_position(
+// Liquidates the position of the trader if its margin ratio is below the
+// maintenance margin ratio, the position is closed in the reply
+pub fn liquidate(
     deps: DepsMut,
     _env: Env,
+    info: MessageInfo,
+    vamm: String,
+    trader: String,
+) -> StdResult<Response> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check that it is a registered vamm
+    let vamm_list: VammList = read_vamm(deps.storage)?;
+    if !vamm_list.is_vamm(&vamm) {
+        return Err(StdError::generic_err("vAMM is not registered"));
+    }
+
+    // validate address inputs
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => position,
+        _ => return Err(StdError::generic_err("no position to liquidate")),
+    };
+
+    let margin_ratio = get_margin_ratio(deps.as_ref(), &position)?;
+    if margin_ratio >= SignedDecimal::from(config.maintenance_margin_ratio) {
+        return Err(StdError::generic_err("position is not undercollateralized"));
+    }
+
+    // if the margin can still cover the liquidation fee only part of the
+    // position is closed, otherwise it is too far underwater and all of it is
+    let is_partial = !config.partial_liquidation_ratio.is_zero()
+        && margin_ratio > SignedDecimal::from(config.liquidation_fee);
+
+    let (base_asset_amount, reply_id) = if is_partial {
+        let amount = multiply_by_ratio(
+            position.size.abs(),
+            config.partial_liquidation_ratio,
+            config.decimals,
+        )?;
+        (amount, PARTIAL_LIQUIDATION_REPLY_ID)
+    } else {
+        (position.size.abs(), LIQUIDATION_REPLY_ID)
+    };
+
+    let swap_msg = WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::SwapOutput {
+            direction: close_direction(&position.size),
+            base_asset_amount,
+            quote_asset_amount_limit: None,
+        })?,
+    };
+
+    let msg = SubMsg {
+        msg: CosmosMsg::Wasm(swap_msg),
+        gas_limit: None, // probably should set a limit in the config
+        id: reply_id,
+        reply_on: ReplyOn::Always,
+    };
+
+    // closing a long is a sell and closing a short is a buy
+    let side = if position.size.is_negative() { Side::BUY } else { Side::SELL };
+    store_tmp_swap(deps.storage, &TmpSwapInfo { position, side })?;
+    store_tmp_liquidator(deps.storage, &info.sender)?;
+
+    Ok(Response::new()
+        .add_submessage(msg)
+        .add_attributes(vec![
+            ("action", "liquidate"),
+            ("vamm", vamm.as_str()),
+            ("trader", trader.as_str()),
+            ("margin_ratio", &margin_ratio.to_string()),
+            ("partial", &is_partial.to_string()),
+        ])
+    )
+}
+
+// Closes the partial liquidation ratio of the position, the liquidator is paid
+// the liquidation fee of the notional that was closed out of the margin and the
+// rest of the position stays open. If the margin can't cover the part that was
+// closed the rest is swapped too and the position is liquidated in full
+pub fn finalize_partial_liquidation(
+    mut deps: DepsMut,
+    env: Env,
+    input: Uint128,
+    output: Uint128,
+) -> StdResult<Response> {
+    let config: Config = read_config(deps.storage)?;
+
+    let tmp_swap = read_tmp_swap(deps.storage)?;
+    let liquidator = read_tmp_liquidator(deps.storage)?;
+    if tmp_swap.is_none() || liquidator.is_none() {
+        return Err(StdError::generic_err("no temporary position"));
+    }
+
+    let TmpSwapInfo { mut position, side } = tmp_swap.unwrap();
+    let liquidator = liquidator.unwrap();
+
+    // the open notional of the part of the position that was closed
+    let size = position.size.abs();
+    let liquidated_notional = multiply_by_ratio(position.notional, input, size)?;
+
+    // closing the rest now would sum to what the whole position was worth
+    // before the swap, the pnl is realized pro-rata of that
+    let remaining_notional = query_vamm_output_price(
+        &deps.as_ref(),
+        position.vamm.to_string(),
+        close_direction(&position.size),
+        size.checked_sub(input)?,
+    )?;
+    let unrealized_pnl = calc_pnl(&position, output.checked_add(remaining_notional)?)?;
+    let realized_pnl = unrealized_pnl.checked_multiply_ratio(
+        SignedDecimal::from(input),
+        SignedDecimal::from(size),
+    )?;
+    let liquidation_fee = multiply_by_ratio(output, config.liquidation_fee, config.decimals)?;
+
+    let funding_payment = calc_funding_payment(deps.as_ref(), &position)?;
+
+    let remaining_margin = SignedDecimal::from(position.margin)
+        .checked_add(realized_pnl)?
+        .checked_sub(SignedDecimal::from(liquidation_fee))?
+        .checked_sub(funding_payment)?;
+    if !remaining_margin.is_positive() {
+        // the margin can't cover closing only part of the position so the
+        // rest of it is closed too and it is liquidated in full
+        let remaining_size = size.checked_sub(input)?;
+        if remaining_size.is_zero() {
+            return finalize_liquidation(deps, env, input, output);
+        }
+
+        store_tmp_exchanged(deps.storage, &output)?;
+
+        let swap_msg = WasmMsg::Execute {
+            contract_addr: position.vamm.to_string(),
+            funds: vec![],
+            msg: to_binary(&ExecuteMsg::SwapOutput {
+                direction: close_direction(&position.size),
+                base_asset_amount: remaining_size,
+                quote_asset_amount_limit: None,
+            })?,
+        };
+
+        let msg = SubMsg {
+            msg: CosmosMsg::Wasm(swap_msg),
+            gas_limit: None, // probably should set a limit in the config
+            id: LIQUIDATION_REPLY_ID,
+            reply_on: ReplyOn::Always,
+        };
+
+        return Ok(Response::new()
+            .add_submessage(msg)
+            .add_attribute("partial", "false")
+        );
+    }
+
+    update_open_interest_notional(
+        &mut deps,
+        &position.vamm,
+        -SignedDecimal::from(liquidated_notional),
+    )?;
+
+    // the swap is on the opposite side to the position so it reduces the size
+    position.size = position.size.checked_add(signed_size(input, &side))?;
+    position.notional = position.notional.checked_sub(liquidated_notional)?;
+    position.margin = remaining_margin.abs();
+    position.premium_fraction = latest_cumulative_premium_fraction(
+        deps.as_ref(),
+        &position.vamm,
+    )?;
+    store_position(deps.storage, &position)?;
+
+    remove_tmp_swap(deps.storage);
+    remove_tmp_liquidator(deps.storage);
+
+    let mut response = Response::new();
+    if !liquidation_fee.is_zero() {
+        response = response.add_submessage(
+            execute_transfer(&config, &liquidator, liquidation_fee)?
+        );
+    }
+
+    Ok(response.add_attributes(vec![
+        ("realized_pnl", &realized_pnl.to_string()),
+        ("liquidation_fee", &liquidation_fee.to_string()),
+        ("liquidated_size", &input.to_string()),
+        ("funding_payment", &funding_payment.to_string()),
+    ]))
+}
+
+// Pays the liquidator the liquidation fee of the notional the position closed
+// for, and books what is left of the margin to the insurance fund
+pub fn finalize_liquidation(
+    mut deps: DepsMut,
+    _env: Env,
     _input: Uint128,
     output: Uint128,
 ) -> StdResult<Response> {
-    let tmp_position = read_tmp_position(deps.storage)?;
-    if tmp_position.is_none() {
+    let config: Config = read_config(deps.storage)?;
+
+    let tmp_swap = read_tmp_swap(deps.storage)?;
+    let liquidator = read_tmp_liquidator(deps.storage)?;
+    if tmp_swap.is_none() || liquidator.is_none() {
         return Err(StdError::generic_err("no temporary position"));
     }
 
-    // TODO update the position with what actually happened in the
-    // swap, probably later this requires to check if long, short,buy, sell
-    // but for now lets just implement the long case
-    let mut position: Position = tmp_position.unwrap();
-    position.size = position.size.checked_add(output)?;
+    let position: Position = tmp_swap.unwrap().position;
+    let liquidator = liquidator.unwrap();
 
-    // store the updated position
+    // a partial liquidation that fell back to a full one has already
+    // exchanged part of the position
+    let output = output.checked_add(read_tmp_exchanged(deps.storage)?)?;
+
+    let realized_pnl = calc_pnl(&position, output)?;
+    let liquidation_fee = multiply_by_ratio(output, config.liquidation_fee, config.decimals)?;
+
+    let funding_payment = calc_funding_payment(deps.as_ref(), &position)?;
+
+    // the margin left after the loss, the fee and funding, if it is negative
+    // the insurance fund covers the shortfall
+    let remaining_margin = SignedDecimal::from(position.margin)
+        .checked_add(realized_pnl)?
+        .checked_sub(SignedDecimal::from(liquidation_fee))?
+        .checked_sub(funding_payment)?;
+
+    update_open_interest_notional(
+        &mut deps,
+        &position.vamm,
+        -SignedDecimal::from(position.notional),
+    )?;
+
+    // the position is closed so there is nothing left to keep
+    remove_position(deps.storage, &position);
+
+    remove_tmp_swap(deps.storage);
+    remove_tmp_liquidator(deps.storage);
+    remove_tmp_exchanged(deps.storage);
+
+    let mut response = Response::new();
+    if !liquidation_fee.is_zero() {
+        response = response.add_submessage(
+            execute_transfer(&config, &liquidator, liquidation_fee)?
+        );
+    }
+
+    if remaining_margin.is_positive() {
+        response = response.add_submessage(
+            execute_transfer(&config, &config.insurance_fund, remaining_margin.abs())?
+        );
+    }
+
+    // the loss beyond the margin is bad debt
+    let shortfall = if remaining_margin.is_negative() {
+        remaining_margin.abs()
+    } else {
+        Uint128::zero()
+    };
+
+    if !shortfall.is_zero() {
+        let (msg, event) = realize_bad_debt(&mut deps, &config, &position.vamm, shortfall)?;
+        response = response.add_submessage(msg).add_event(event);
+    }
+
+    Ok(response.add_attributes(vec![
+        ("realized_pnl", &realized_pnl.to_string()),
+        ("liquidation_fee", &liquidation_fee.to_string()),
+        ("funding_payment", &funding_payment.to_string()),
+        ("shortfall", &shortfall.to_string()),
+    ]))
+}
+
+// Adds margin to the position of the trader, the funds are transferred from the
+// trader unless they have already been sent with the cw20 hook
+pub fn add_margin(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    vamm: String,
+    trader: String,
+    amount: Uint128,
+    transfer: bool,
+) -> StdResult<Response> {
+    // check that it is a registered vamm
+    let vamm_list: VammList = read_vamm(deps.storage)?;
+    if !vamm_list.is_vamm(&vamm) {
+        return Err(StdError::generic_err("vAMM is not registered"));
+    }
+
+    // validate address inputs
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("margin amount must be greater than zero"));
+    }
+
+    let mut position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => position,
+        _ => return Err(StdError::generic_err("no position to add margin to")),
+    };
+
+    // the added margin counts towards the funding owed
+    position.margin = position.margin.checked_add(amount)?;
+    apply_funding_payment(deps.as_ref(), &mut position)?;
     store_position(deps.storage, &position)?;
 
+    let mut response = Response::new();
+    if transfer {
+        response = response.add_submessage(execute_transfer_from(
+            deps.storage,
+            &trader,
+            &env.contract.address,
+            amount,
+        )?);
+    }
+
+    Ok(response.add_attributes(vec![
+        ("action", "add_margin"),
+        ("vamm", vamm.as_str()),
+        ("trader", trader.as_str()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Removes margin from the position of the trader, the position has to stay
+// above the initial margin ratio with its unrealized pnl counted
+pub fn remove_margin(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    vamm: String,
+    trader: String,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check that it is a registered vamm
+    let vamm_list: VammList = read_vamm(deps.storage)?;
+    if !vamm_list.is_vamm(&vamm) {
+        return Err(StdError::generic_err("vAMM is not registered"));
+    }
+
+    // validate address inputs
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("margin amount must be greater than zero"));
+    }
+
+    let mut position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => position,
+        _ => return Err(StdError::generic_err("no position to remove margin from")),
+    };
+
+    apply_funding_payment(deps.as_ref(), &mut position)?;
+
+    if amount > position.margin {
+        return Err(StdError::generic_err("insufficient margin"));
+    }
+
+    position.margin = position.margin.checked_sub(amount)?;
+
+    let margin_ratio = get_margin_ratio(deps.as_ref(), &position)?;
+    if margin_ratio < SignedDecimal::from(config.initial_margin_ratio) {
+        return Err(StdError::generic_err("position would be below the initial margin ratio"));
+    }
+
+    store_position(deps.storage, &position)?;
+
+    Ok(Response::new()
+        .add_submessage(execute_transfer(&config, &trader, amount)?)
+        .add_attributes(vec![
+            ("action", "remove_margin"),
+            ("vamm", vamm.as_str()),
+            ("trader", trader.as_str()),
+            ("amount", &amount.to_string()),
+        ])
+    )
+}
+
+// Settles funding on the vamm, positions pay the change in the vamm's
+// cumulative premium fraction when they are next touched
+pub fn pay_funding(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    vamm: String,
+) -> StdResult<Response> {
+    // check that it is a registered vamm
+    let vamm_list: VammList = read_vamm(deps.storage)?;
+    if !vamm_list.is_vamm(&vamm) {
+        return Err(StdError::generic_err("vAMM is not registered"));
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    let funding_msg = WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::SettleFunding {})?,
+    };
+
+    let msg = SubMsg {
+        msg: CosmosMsg::Wasm(funding_msg),
+        gas_limit: None, // probably should set a limit in the config
+        id: PAY_FUNDING_REPLY_ID,
+        reply_on: ReplyOn::Success,
+    };
+
+    Ok(Response::new()
+        .add_submessage(msg)
+        .add_attributes(vec![
+            ("action", "pay_funding"),
+            ("vamm", vamm.as_str()),
+        ])
+    )
+}
+
+// Settles the imbalance between longs and shorts against the insurance fund
+// once the vamm has settled funding. The positions pay the premium fraction
+// on the total position size of the vamm, what the larger side pays beyond
+// what the other side receives goes to the fund, and if the larger side is
+// owed more the fund pays it, up to what it holds
+pub fn finalize_pay_funding(
+    deps: DepsMut,
+    _env: Env,
+    vamm: String,
+    premium_fraction: SignedDecimal,
+) -> StdResult<Response> {
+    let config: Config = read_config(deps.storage)?;
+    let vamm_state = query_vamm_state(&deps.as_ref(), vamm.clone())?;
+
+    let funding_imbalance = premium_fraction.checked_multiply_ratio(
+        vamm_state.total_position_size,
+        SignedDecimal::from(config.decimals),
+    )?;
+
+    let mut response = Response::new();
+    if funding_imbalance.is_positive() {
+        response = response.add_submessage(
+            execute_transfer(&config, &config.insurance_fund, funding_imbalance.abs())?
+        );
+    } else if funding_imbalance.is_negative() {
+        let fund_balance = query_insurance_fund_balance(
+            &deps.as_ref(),
+            config.insurance_fund.to_string(),
+        )?;
+        let payout = funding_imbalance.abs().min(fund_balance);
+        if !payout.is_zero() {
+            response = response.add_submessage(payout_from_insurance_fund(&config, payout)?);
+        }
+    }
+
+    Ok(response.add_attributes(vec![
+        ("vamm", vamm.as_str()),
+        ("funding_imbalance", &funding_imbalance.to_string()),
+    ]))
+}
+
+// Settles the position of the trader at the settlement price of a vamm that
+// has been shut down, the margin and realized pnl are returned to the trader
+pub fn settle_position(
+    mut deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    vamm: String,
+    trader: String,
+) -> StdResult<Response> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check that it is a registered vamm
+    let vamm_list: VammList = read_vamm(deps.storage)?;
+    if !vamm_list.is_vamm(&vamm) {
+        return Err(StdError::generic_err("vAMM is not registered"));
+    }
+
+    // validate address inputs
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => position,
+        _ => return Err(StdError::generic_err("no position to settle")),
+    };
+
+    // only a shut down market has a settlement price, a market that is
+    // just closed can be reopened
+    let vamm_state = query_vamm_state(&deps.as_ref(), vamm.to_string())?;
+    if !vamm_state.shutdown {
+        return Err(StdError::generic_err("vAMM is not shut down"));
+    }
+
+    // the position is worth its size at the settlement price, it is paid out
+    // like any other close but without fees
+    let settled_notional = multiply_by_ratio(
+        position.size.abs(),
+        vamm_state.settlement_price,
+        vamm_state.decimals,
+    )?;
+    let (response, _) = settle_closed_position(
+        &mut deps,
+        &env,
+        &config,
+        &position,
+        settled_notional,
+        Uint128::zero(),
+    )?;
+
+    // the position is closed so there is nothing left to keep
+    remove_position(deps.storage, &position);
+
+    Ok(response.add_attributes(vec![
+        ("action", "settle_position"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Closes position returning funds after successful execution of the swap out
+pub fn finalize_close_position(
+    mut deps: DepsMut,
+    env: Env,
+    _input: Uint128,
+    output: Uint128,
+) -> StdResult<Response> {
+    let tmp_swap = read_tmp_swap(deps.storage)?;
+    if tmp_swap.is_none() {
+        return Err(StdError::generic_err("no temporary position"));
+    }
+
+    let config: Config = read_config(deps.storage)?;
+    let position: Position = tmp_swap.unwrap().position;
+
+    // fees are charged on the notional the position closes for and come
+    // out of what is returned to the trader
+    let fees = query_vamm_calc_fee(&deps.as_ref(), position.vamm.to_string(), output)?;
+
+    let (mut response, charged_fees) = settle_closed_position(
+        &mut deps,
+        &env,
+        &config,
+        &position,
+        output,
+        fees.toll_fee.checked_add(fees.spread_fee)?,
+    )?;
+
+    // what is left of the margin pays the toll before the spread
+    let toll_fee = fees.toll_fee.min(charged_fees);
+    let spread_fee = charged_fees.checked_sub(toll_fee)?;
+
+    if !toll_fee.is_zero() {
+        response = response.add_submessage(
+            execute_transfer(&config, &config.fee_pool, toll_fee)?
+        );
+    }
+
+    if !spread_fee.is_zero() {
+        response = response.add_submessage(
+            execute_transfer(&config, &config.insurance_fund, spread_fee)?
+        );
+    }
+
+    // the position is closed so there is nothing left to keep
+    remove_position(deps.storage, &position);
+
     // remove the tmp position
-    remove_tmp_position(deps.storage);
+    remove_tmp_swap(deps.storage);
 
-    Ok(Response::new())
+    Ok(response)
+}
+
+// Realizes the pnl of a position that closed for the output and pays the
+// trader what is left of the margin after funding and the fees. The fees are
+// only charged up to what is left of the margin and are left with the engine
+// for the caller to send on, so the fees charged are returned with the
+// response. A loss beyond the margin is bad debt.
+fn settle_closed_position(
+    deps: &mut DepsMut,
+    env: &Env,
+    config: &Config,
+    position: &Position,
+    output: Uint128,
+    fees: Uint128,
+) -> StdResult<(Response, Uint128)> {
+    let (realized_pnl, socialized_loss) = socialize_loss(
+        deps.storage,
+        position,
+        calc_pnl(position, output)?,
+    )?;
+    let funding_payment = calc_funding_payment(deps.as_ref(), position)?;
+
+    let remaining_margin = SignedDecimal::from(position.margin)
+        .checked_add(realized_pnl)?
+        .checked_sub(funding_payment)?;
+    let (mut withdrawn_amount, mut charged_fees) = if remaining_margin.is_negative() {
+        (Uint128::zero(), Uint128::zero())
+    } else {
+        let charged_fees = fees.min(remaining_margin.abs());
+        (remaining_margin.abs().checked_sub(charged_fees)?, charged_fees)
+    };
+
+    let mut response = Response::new();
+
+    // profits are paid out of the margin of other traders, if the engine
+    // doesn't hold enough the insurance fund makes up the difference. What
+    // the fund can't cover either isn't paid and is bad debt
+    let total_out = withdrawn_amount.checked_add(charged_fees)?;
+    let balance = query_token_balance(
+        &deps.as_ref(),
+        config.eligible_collateral.to_string(),
+        env.contract.address.to_string(),
+    )?;
+    if balance < total_out {
+        let fund_balance = query_insurance_fund_balance(
+            &deps.as_ref(),
+            config.insurance_fund.to_string(),
+        )?;
+        let available = balance.checked_add(fund_balance)?;
+
+        if available < total_out {
+            let shortfall = total_out.checked_sub(available)?;
+            let unpaid = shortfall.min(withdrawn_amount);
+            withdrawn_amount = withdrawn_amount.checked_sub(unpaid)?;
+            charged_fees = charged_fees.checked_sub(shortfall.checked_sub(unpaid)?)?;
+
+            let event = record_bad_debt(
+                deps.storage,
+                config,
+                &position.vamm,
+                shortfall,
+                Uint128::zero(),
+            )?;
+            response = response.add_event(event);
+        }
+
+        let payout = available.min(total_out).checked_sub(balance)?;
+        if !payout.is_zero() {
+            response = response.add_submessage(
+                payout_from_insurance_fund(config, payout)?
+            );
+        }
+    }
+
+    if remaining_margin.is_negative() {
+        let (msg, event) = realize_bad_debt(
+            deps,
+            config,
+            &position.vamm,
+            remaining_margin.abs(),
+        )?;
+        response = response.add_submessage(msg).add_event(event);
+    }
+
+    // the position no longer counts towards the open interest
+    update_open_interest_notional(
+        deps,
+        &position.vamm,
+        -SignedDecimal::from(position.notional),
+    )?;
+
+    if !withdrawn_amount.is_zero() {
+        response = response.add_submessage(
+            execute_transfer(config, &position.trader, withdrawn_amount)?
+        );
+    }
+
+    let response = response
+        .add_attributes(vec![
+            ("realized_pnl", &realized_pnl.to_string()),
+            ("funding_payment", &funding_payment.to_string()),
+            ("socialized_loss", &socialized_loss.to_string()),
+            ("withdrawn_amount", &withdrawn_amount.to_string()),
+        ]);
+
+    Ok((response, charged_fees))
 }
 
 // Increases position after successful execution of the swap
 pub fn increase_position(
-    deps: DepsMut,
+    mut deps: DepsMut,
     _env: Env,
-    _input: Uint128,
+    input: Uint128,
     output: Uint128,
 ) -> StdResult<Response> {
-    let tmp_position = read_tmp_position(deps.storage)?;
-    if tmp_position.is_none() {
+    let tmp_swap = read_tmp_swap(deps.storage)?;
+    if tmp_swap.is_none() {
         return Err(StdError::generic_err("no temporary position"));
     }
 
-    // TODO update the position with what actually happened in the
-    // swap, probably later this requires to check if long, short,buy, sell
-    // but for now lets just implement the long case
-    let mut position: Position = tmp_position.unwrap();
-    position.size = position.size.checked_add(output)?;
+    let TmpSwapInfo { mut position, side } = tmp_swap.unwrap();
+    position.size = position.size.checked_add(signed_size(output, &side))?;
+
+    // the trader can hold at most the max holding of the vamm
+    let vamm_config = query_vamm_config(&deps.as_ref(), position.vamm.to_string())?;
+    if !vamm_config.max_holding_base_asset.is_zero()
+        && position.size.abs() > vamm_config.max_holding_base_asset {
+        return Err(StdError::generic_err("position is over the max holding base asset"));
+    }
+
+    update_open_interest_notional(
+        &mut deps,
+        &position.vamm,
+        SignedDecimal::from(input),
+    )?;
 
     // store the updated position
     store_position(deps.storage, &position)?;
 
     // remove the tmp position
-    remove_tmp_position(deps.storage);
+    remove_tmp_swap(deps.storage);
 
     Ok(Response::new())
 }
 
 // Decreases position after successful execution of the swap
 pub fn decrease_position(
-    deps: DepsMut,
+    mut deps: DepsMut,
     _env: Env,
-    _input: Uint128,
+    input: Uint128,
     output: Uint128,
 ) -> StdResult<Response> {
-    let tmp_position = read_tmp_position(deps.storage)?;
-    if tmp_position.is_none() {
+    let tmp_swap = read_tmp_swap(deps.storage)?;
+    if tmp_swap.is_none() {
         return Err(StdError::generic_err("no temporary position"));
     }
-    // TODO update the position with what actually happened in the
-    // swap, probably later this requires to check if long, short,buy, sell
-    // but for now lets just implement the long case
-    let mut position: Position = tmp_position.unwrap();
-    position.size = position.size.checked_sub(output)?;
+
+    let TmpSwapInfo { mut position, side } = tmp_swap.unwrap();
+
+    // the open notional of the part of the position that was closed, it
+    // closed for the input so the pnl of that part is realized into the margin
+    let reduced_notional = multiply_by_ratio(position.notional, output, position.size.abs())?;
+    let realized_pnl = calc_pnl(
+        &Position {
+            notional: reduced_notional,
+            ..position.clone()
+        },
+        input,
+    )?;
+
+    let margin = SignedDecimal::from(position.margin).checked_add(realized_pnl)?;
+    if margin.is_negative() {
+        return Err(StdError::generic_err("margin is insufficient to realize the loss"));
+    }
+
+    // the swap is on the opposite side to the position so it reduces the size
+    position.size = position.size.checked_add(signed_size(output, &side))?;
+    position.notional = position.notional.checked_sub(reduced_notional)?;
+    position.margin = margin.abs();
+
+    update_open_interest_notional(
+        &mut deps,
+        &position.vamm,
+        -SignedDecimal::from(reduced_notional),
+    )?;
 
     // store the updated position
     store_position(deps.storage, &position)?;
 
     // remove the tmp position
-    remove_tmp_position(deps.storage);
+    remove_tmp_swap(deps.storage);
 
-    Ok(Response::new())
+    Ok(Response::new().add_attributes(vec![
+        ("realized_pnl", &realized_pnl.to_string()),
+    ]))
 }
 
-// Decreases position after successful execution of the swap
+// Closes the position that is reversed after the swap and opens what is left
+// of the trade on the other side
 pub fn reverse_position(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     _input: Uint128,
     output: Uint128,
 ) -> StdResult<Response> {
-    let mut response: Response = Response::new();
-
-    let tmp_position = read_tmp_position(deps.storage)?;
-    if tmp_position.is_none() {
+    let tmp_swap = read_tmp_swap(deps.storage)?;
+    let tmp_reverse = read_tmp_reverse(deps.storage)?;
+    if tmp_swap.is_none() || tmp_reverse.is_none() {
         return Err(StdError::generic_err("no temporary position"));
     }
 
-    let open_notional = tmp_position.clone().unwrap().notional;
-    let amount = open_notional
-        .checked_sub(output)?;
+    let config: Config = read_config(deps.storage)?;
+    let TmpSwapInfo { position, side } = tmp_swap.unwrap();
+    let TmpReverseInfo {
+        quote_asset_amount,
+        open_notional,
+        leverage,
+        base_asset_amount_limit,
+        transfer,
+    } = tmp_reverse.unwrap();
+
+    // the fees were charged on the whole of the trade when it was opened
+    let (mut response, _) = settle_closed_position(
+        &mut deps,
+        &env,
+        &config,
+        &position,
+        output,
+        Uint128::zero(),
+    )?;
+
+    remove_tmp_reverse(deps.storage);
+
+    // the position closed for at most the trade, if it closed for all of it
+    // there is nothing left to open
+    let remaining_notional = open_notional.saturating_sub(output);
+
+    // rounding in the vamm can leave dust of the trade once the position has
+    // closed, anything below the fourth decimal place isn't worth opening
+    let dust = config.decimals.checked_div(Uint128::from(10_000u128))?;
+    if remaining_notional <= dust {
+        remove_position(deps.storage, &position);
+        remove_tmp_swap(deps.storage);
+
+        // what was sent with the trade isn't needed as margin
+        if !transfer {
+            response = response.add_submessage(
+                execute_transfer(&config, &position.trader, quote_asset_amount)?
+            );
+        }
 
+        return Ok(response);
+    }
 
-    // so if the position to reverse is large then we do something, if it is smaller than a few wei
-    // just reset the position and move on with life
-    let mut position = clear_position(env, tmp_position.clone().unwrap())?;
-    store_position(deps.storage, &position)?;
+    // the new position is margined at the leverage of the trade
+    let margin = multiply_by_ratio(remaining_notional, config.decimals, leverage)?;
+    let position = Position {
+        vamm: position.vamm.clone(),
+        trader: position.trader.clone(),
+        margin,
+        notional: remaining_notional,
+        premium_fraction: latest_cumulative_premium_fraction(
+            deps.as_ref(),
+            &position.vamm,
+        )?,
+        liquidity_history_index: latest_liquidity_history_index(
+            deps.as_ref(),
+            &position.vamm,
+        )?,
+        timestamp: env.block.time,
+        ..Position::default()
+    };
 
-    // TODO, this is hardcoded to close and clear if the amount is less than the smallest 4dp of you precision
-    // not for production
-    if amount > Uint128::from(1000u128) {
-        let direction = switch_direction(position.direction.clone());
+    // the margin is taken from the trader, or if it was sent with the trade
+    // what is left of it is returned
+    if transfer {
+        response = response.add_submessage(execute_transfer_from(
+            deps.storage,
+            &position.trader,
+            &env.contract.address,
+            margin,
+        )?);
+    } else {
+        let refund = quote_asset_amount.saturating_sub(margin);
+        if !refund.is_zero() {
+            response = response.add_submessage(
+                execute_transfer(&config, &position.trader, refund)?
+            );
+        }
+    }
 
-        // then we are opening a new position or adding to an existing
-        let msg = swap_input(
-            &position.vamm,
-            direction_to_side(direction),
-            amount,
-            SWAP_INCREASE_REPLY_ID
-        ).unwrap();
-    
-        // increase the margin, notional etc...
-        position.margin = position.margin.checked_add(amount)?;
-        position.notional = position.notional.checked_add(open_notional)?;
-    
-        // store the updated position
-        store_tmp_position(deps.storage, &position)?;
+    let swap_msg = swap_input(
+        &position.vamm,
+        side.clone(),
+        remaining_notional,
+        base_asset_amount_limit,
+        SWAP_INCREASE_REPLY_ID
+    )?;
 
-        // add the response
-        response = response.add_submessage(msg);
-    
+    store_tmp_swap(deps.storage, &TmpSwapInfo { position, side })?;
+
+    Ok(response.add_submessage(swap_msg))
+}
+
+// the interval (in seconds) of the twap that positions are valued by
+pub const TWAP_INTERVAL: u64 = 900;
+
+/// Returns the notional of the position and its unrealized pnl. The notional
+/// is what the position would close for at the spot price, or its size valued
+/// at the twap of the vamm or the oracle price.
+pub fn get_position_notional_unrealized_pnl(
+    deps: Deps,
+    position: &Position,
+    calc_option: PNLCalc,
+) -> StdResult<(Uint128, SignedDecimal)> {
+    let config: Config = read_config(deps.storage)?;
+    let vamm = position.vamm.to_string();
+    let size = position.size.abs();
+
+    let position_notional = match calc_option {
+        PNLCalc::SPOTPRICE => {
+            query_vamm_output_price(&deps, vamm, close_direction(&position.size), size)?
+        }
+        PNLCalc::TWAP => {
+            multiply_by_ratio(
+                query_vamm_twap(&deps, vamm, TWAP_INTERVAL)?,
+                size,
+                config.decimals,
+            )?
+        }
+        PNLCalc::ORACLE => {
+            multiply_by_ratio(
+                query_vamm_underlying_price(&deps, vamm)?,
+                size,
+                config.decimals,
+            )?
+        }
+    };
+
+    Ok((position_notional, calc_pnl(position, position_notional)?))
+}
+
+/// Returns true if the spot price of the vamm has moved further from the
+/// oracle price than the spread limit ratio, a ratio of zero disables it
+pub fn is_over_spread_limit(
+    deps: Deps,
+    vamm: &Addr,
+) -> StdResult<bool> {
+    let config: Config = read_config(deps.storage)?;
+    if config.spread_limit_ratio.is_zero() {
+        return Ok(false);
     }
-    
-    Ok(response)
+
+    let oracle_price = query_vamm_underlying_price(&deps, vamm.to_string())?;
+    let spot_price = query_vamm_spot_price(&deps, vamm.to_string())?;
+
+    let spread = if spot_price > oracle_price {
+        spot_price - oracle_price
+    } else {
+        oracle_price - spot_price
+    };
+    let spread_ratio = multiply_by_ratio(spread, config.decimals, oracle_price)?;
+
+    Ok(spread_ratio > config.spread_limit_ratio)
 }
 
-// this resets the main variables of a position
-fn clear_position(
-    env: Env,
-    mut position: Position,
-) -> StdResult<Position> {
-    position.size = Uint128::zero();
-    position.margin = Uint128::zero();
-    position.notional = Uint128::zero();
-    position.timestamp = env.block.time;
+/// Returns the margin ratio of the position, i.e. its margin plus unrealized
+/// pnl over its notional. The position is valued at the spot price unless
+/// the spot price is over the spread limit, when the oracle price is used so
+/// a manipulated spot price can't make positions liquidatable.
+pub fn get_margin_ratio(
+    deps: Deps,
+    position: &Position,
+) -> StdResult<SignedDecimal> {
+    let calc_option = if is_over_spread_limit(deps, &position.vamm)? {
+        PNLCalc::ORACLE
+    } else {
+        PNLCalc::SPOTPRICE
+    };
+
+    calc_margin_ratio(deps, position, calc_option)
+}
+
+/// Returns the margin ratio of the position with it valued by the calc option,
+/// a position with no size has a margin ratio of zero.
+pub fn calc_margin_ratio(
+    deps: Deps,
+    position: &Position,
+    calc_option: PNLCalc,
+) -> StdResult<SignedDecimal> {
+    let config: Config = read_config(deps.storage)?;
+
+    if position.size.is_zero() {
+        return Ok(SignedDecimal::zero());
+    }
+
+    let (position_notional, unrealized_pnl) = get_position_notional_unrealized_pnl(
+        deps,
+        position,
+        calc_option,
+    )?;
+
+    let funding_payment = calc_funding_payment(deps, position)?;
+
+    let remaining_margin = SignedDecimal::from(position.margin)
+        .checked_add(unrealized_pnl)?
+        .checked_sub(funding_payment)?;
+
+    remaining_margin.checked_multiply_ratio(
+        SignedDecimal::from(config.decimals),
+        SignedDecimal::from(position_notional),
+    )
+}
+
+/// Returns the funding the position owes since its premium fraction was last
+/// updated, a positive premium fraction is paid by longs to shorts so a
+/// negative payment is funding the position receives.
+pub fn calc_funding_payment(
+    deps: Deps,
+    position: &Position,
+) -> StdResult<SignedDecimal> {
+    let config: Config = read_config(deps.storage)?;
+    let latest = latest_cumulative_premium_fraction(deps, &position.vamm)?;
+
+    latest
+        .checked_sub(position.premium_fraction)?
+        .checked_multiply_ratio(position.size, SignedDecimal::from(config.decimals))
+}
+
+// settles the funding the position owes out of its margin and updates it to
+// the latest premium fraction
+fn apply_funding_payment(
+    deps: Deps,
+    position: &mut Position,
+) -> StdResult<SignedDecimal> {
+    let funding_payment = calc_funding_payment(deps, position)?;
+
+    let remaining_margin = SignedDecimal::from(position.margin)
+        .checked_sub(funding_payment)?;
+    if remaining_margin.is_negative() {
+        return Err(StdError::generic_err("margin is insufficient to pay funding"));
+    }
+
+    position.margin = remaining_margin.abs();
+    position.premium_fraction = latest_cumulative_premium_fraction(
+        deps,
+        &position.vamm,
+    )?;
 
-    Ok(position)
+    Ok(funding_payment)
+}
+
+// returns the cumulative premium fraction of the vamm, it is the sum of the
+// premium fractions of every funding settlement
+fn latest_cumulative_premium_fraction(
+    deps: Deps,
+    vamm: &Addr,
+) -> StdResult<SignedDecimal> {
+    Ok(query_vamm_state(&deps, vamm.to_string())?.cumulative_premium_fraction)
+}
+
+// a long profits if the position is worth more than it cost to open, and a
+// short if it is worth less
+fn calc_pnl(
+    position: &Position,
+    notional: Uint128,
+) -> StdResult<SignedDecimal> {
+    let notional = SignedDecimal::from(notional);
+    let open_notional = SignedDecimal::from(position.notional);
+
+    if position.size.is_negative() {
+        open_notional.checked_sub(notional)
+    } else {
+        notional.checked_sub(open_notional)
+    }
 }
 
 fn swap_input(
     vamm: &Addr,
     side: Side, 
     open_notional: Uint128, 
+    base_asset_amount_limit: Option<Uint128>,
     id: u64,
 ) -> StdResult<SubMsg> {
     let direction: Direction = side_to_direction(side);
@@ -368,15 +1398,16 @@ fn swap_input(
         contract_addr: vamm.to_string(),
         funds: vec![],
         msg: to_binary(&ExecuteMsg::SwapInput {
-            direction: direction,
+            direction,
             quote_asset_amount: open_notional,
+            base_asset_amount_limit,
         })?,
     };
 
     let execute_submsg = SubMsg {
         msg: CosmosMsg::Wasm(swap_msg),
         gas_limit: None, // probably should set a limit in the config
-        id: id,
+        id,
         reply_on: ReplyOn::Always,
     };
 
@@ -396,7 +1427,64 @@ fn execute_transfer_from(
         msg: to_binary(&Cw20ExecuteMsg::TransferFrom {
             owner: sender.to_string(),
             recipient: receiver.to_string(),
-            amount: amount,
+            amount,
+        })?,
+    };
+
+    let transfer_msg = SubMsg {
+        msg: CosmosMsg::Wasm(msg),
+        gas_limit: None, // probably should set a limit in the config
+        id: 0u64,
+        reply_on: ReplyOn::Never,
+    };
+
+    Ok(transfer_msg)
+}
+
+// transfers the toll fee to the fee pool and the spread fee to the insurance
+// fund from the trader, the fees are charged on the notional of the trade
+fn transfer_fees(
+    deps: Deps,
+    trader: &Addr,
+    vamm: &Addr,
+    notional: Uint128,
+) -> StdResult<Vec<SubMsg>> {
+    let config = read_config(deps.storage)?;
+    let fees = query_vamm_calc_fee(&deps, vamm.to_string(), notional)?;
+
+    let mut messages: Vec<SubMsg> = vec![];
+    if !fees.toll_fee.is_zero() {
+        messages.push(execute_transfer_from(
+            deps.storage,
+            trader,
+            &config.fee_pool,
+            fees.toll_fee,
+        )?);
+    }
+
+    if !fees.spread_fee.is_zero() {
+        messages.push(execute_transfer_from(
+            deps.storage,
+            trader,
+            &config.insurance_fund,
+            fees.spread_fee,
+        )?);
+    }
+
+    Ok(messages)
+}
+
+fn execute_transfer(
+    config: &Config,
+    receiver: &Addr,
+    amount: Uint128,
+) -> StdResult<SubMsg> {
+    let msg = WasmMsg::Execute {
+        contract_addr: config.eligible_collateral.to_string(),
+        funds: vec![],
+        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+            recipient: receiver.to_string(),
+            amount,
         })?,
     };
 
@@ -410,6 +1498,139 @@ fn execute_transfer_from(
     Ok(transfer_msg)
 }
 
+// books bad debt to the vamm and withdraws it from the insurance fund, what the
+// fund can't cover either pauses the vamm or is socialized across profitable
+// positions depending on the bad debt mode
+fn realize_bad_debt(
+    deps: &mut DepsMut,
+    config: &Config,
+    vamm: &Addr,
+    amount: Uint128,
+) -> StdResult<(SubMsg, Event)> {
+    let balance = query_insurance_fund_balance(
+        &deps.as_ref(),
+        config.insurance_fund.to_string(),
+    )?;
+    let event = record_bad_debt(deps.storage, config, vamm, amount, amount.min(balance))?;
+
+    // the withdrawal is the full amount so the fund records the deficit
+    Ok((withdraw_from_insurance_fund(config, amount)?, event))
+}
+
+// books bad debt to the vamm of which the insurance fund covers some, what
+// isn't covered either pauses the vamm or is socialized
+fn record_bad_debt(
+    storage: &mut dyn Storage,
+    config: &Config,
+    vamm: &Addr,
+    amount: Uint128,
+    covered: Uint128,
+) -> StdResult<Event> {
+    let uncovered = amount.checked_sub(covered)?;
+
+    let mut bad_debt = read_bad_debt(storage, vamm)?;
+    bad_debt.total = bad_debt.total.checked_add(amount)?;
+    if !uncovered.is_zero() {
+        match config.bad_debt_mode {
+            BadDebtMode::PAUSE => bad_debt.paused = true,
+            BadDebtMode::SOCIALIZE => {
+                bad_debt.socialized_loss = bad_debt.socialized_loss.checked_add(uncovered)?;
+            }
+        }
+    }
+    store_bad_debt(storage, vamm, &bad_debt)?;
+
+    Ok(Event::new("bad_debt")
+        .add_attributes(vec![
+            ("vamm", vamm.as_str()),
+            ("amount", &amount.to_string()),
+            ("covered", &covered.to_string()),
+            ("uncovered", &uncovered.to_string()),
+            ("paused", &bad_debt.paused.to_string()),
+            ("socialized_loss", &bad_debt.socialized_loss.to_string()),
+        ]))
+}
+
+// takes the position's share of the socialized loss of the vamm out of its
+// profit, returns the profit that is left and how much of the loss it paid.
+// The share is pro rata of the position's notional to the open interest of
+// the vamm, so each position that closes in profit pays for its part of the
+// open interest rather than the first one paying all of it. A position only
+// pays up to its profit, what is left is shared by the positions still open.
+fn socialize_loss(
+    storage: &mut dyn Storage,
+    position: &Position,
+    realized_pnl: SignedDecimal,
+) -> StdResult<(SignedDecimal, Uint128)> {
+    let mut bad_debt = read_bad_debt(storage, &position.vamm)?;
+    if !realized_pnl.is_positive() || bad_debt.socialized_loss.is_zero() {
+        return Ok((realized_pnl, Uint128::zero()));
+    }
+
+    // the open interest still includes the position, the max keeps the share
+    // at most the whole loss
+    let open_interest = read_open_interest_notional(storage, &position.vamm)?
+        .max(position.notional);
+    let share = multiply_by_ratio(
+        bad_debt.socialized_loss,
+        position.notional,
+        open_interest,
+    )?;
+
+    let loss = share.min(realized_pnl.abs());
+    bad_debt.socialized_loss = bad_debt.socialized_loss.checked_sub(loss)?;
+    store_bad_debt(storage, &position.vamm, &bad_debt)?;
+
+    Ok((realized_pnl.checked_sub(SignedDecimal::from(loss))?, loss))
+}
+
+// withdraws from the insurance fund to the engine to cover bad debt
+fn withdraw_from_insurance_fund(
+    config: &Config,
+    amount: Uint128,
+) -> StdResult<SubMsg> {
+    let msg = WasmMsg::Execute {
+        contract_addr: config.insurance_fund.to_string(),
+        funds: vec![],
+        msg: to_binary(&InsuranceFundExecuteMsg::Withdraw {
+            amount,
+        })?,
+    };
+
+    let withdraw_msg = SubMsg {
+        msg: CosmosMsg::Wasm(msg),
+        gas_limit: None, // probably should set a limit in the config
+        id: 0u64,
+        reply_on: ReplyOn::Never,
+    };
+
+    Ok(withdraw_msg)
+}
+
+// asks the insurance fund for what the engine is short of paying out, unlike
+// a withdrawal this isn't bad debt
+fn payout_from_insurance_fund(
+    config: &Config,
+    amount: Uint128,
+) -> StdResult<SubMsg> {
+    let msg = WasmMsg::Execute {
+        contract_addr: config.insurance_fund.to_string(),
+        funds: vec![],
+        msg: to_binary(&InsuranceFundExecuteMsg::Payout {
+            amount,
+        })?,
+    };
+
+    let payout_msg = SubMsg {
+        msg: CosmosMsg::Wasm(msg),
+        gas_limit: None, // probably should set a limit in the config
+        id: 0u64,
+        reply_on: ReplyOn::Never,
+    };
+
+    Ok(payout_msg)
+}
+
 // takes the side (buy|sell) and returns the direction (long|short)
 fn side_to_direction(
     side: Side,
@@ -419,28 +1640,78 @@ fn side_to_direction(
             Side::SELL => Direction::RemoveFromAmm,
     };
 
-    return direction
+    direction
 }
 
-// takes the direction (long|short) and returns the side (buy|sell)
-fn direction_to_side(
-    direction: Direction,
-) -> Side {
-    let side: Side = match direction {
-            Direction::AddToAmm => Side::BUY,
-            Direction::RemoveFromAmm => Side::SELL,
+// adds the change in notional to the open interest of the vamm, an increase
+// is rejected if it takes the open interest over the cap of the vamm
+fn update_open_interest_notional(
+    deps: &mut DepsMut,
+    vamm: &Addr,
+    amount: SignedDecimal,
+) -> StdResult<()> {
+    let open_interest = read_open_interest_notional(deps.storage, vamm)?;
+
+    let open_interest = if amount.is_negative() {
+        open_interest.saturating_sub(amount.abs())
+    } else {
+        let open_interest = open_interest.checked_add(amount.abs())?;
+
+        let vamm_config = query_vamm_config(&deps.as_ref(), vamm.to_string())?;
+        if !vamm_config.open_interest_notional_cap.is_zero()
+            && open_interest > vamm_config.open_interest_notional_cap {
+            return Err(StdError::generic_err("open interest is over the notional cap"));
+        }
+
+        open_interest
     };
 
-    return side
+    store_open_interest_notional(deps.storage, vamm, &open_interest)
+}
+
+// returns the index of the latest change of liquidity of the vamm
+fn latest_liquidity_history_index(
+    deps: Deps,
+    vamm: &Addr,
+) -> StdResult<Uint128> {
+    let length = query_vamm_liquidity_history_length(&deps, vamm.to_string())?;
+
+    Ok(Uint128::from(length.saturating_sub(1)))
+}
+
+// takes the base asset amount of a swap and signs it by the side, buying
+// is positive and selling is negative
+fn signed_size(
+    amount: Uint128,
+    side: &Side,
+) -> SignedDecimal {
+    match side {
+        Side::BUY => SignedDecimal::from(amount),
+        Side::SELL => -SignedDecimal::from(amount),
+    }
+}
+
+// Returns a * b / c without overflowing in the multiplication
+fn multiply_by_ratio(
+    a: Uint128,
+    b: Uint128,
+    c: Uint128,
+) -> StdResult<Uint128> {
+    let result = a
+        .full_mul(b)
+        .checked_div(c.into())?;
+
+    Ok(Uint128::try_from(result)?)
 }
 
-// takes the side (buy|sell) and returns opposite (short|long)
-// this is useful when closing/reversing a position
-fn switch_direction(
-    dir: Direction,
+// takes the size of a position and returns the direction of the swap out
+// that closes it, a long adds the base asset back and a short removes it
+fn close_direction(
+    size: &SignedDecimal,
 ) -> Direction {
-    return match dir {
-            Direction::RemoveFromAmm => Direction::AddToAmm,
-            Direction::AddToAmm => Direction::RemoveFromAmm,
-    };
+    if size.is_negative() {
+        Direction::RemoveFromAmm
+    } else {
+        Direction::AddToAmm
+    }
 }
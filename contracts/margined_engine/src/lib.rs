@@ -1,5 +1,6 @@
 pub mod contract;
 mod handle;
+mod querier;
 mod query;
 mod state;
 mod error;
@@ -1,17 +1,118 @@
 // Contains queries for external contracts
 use cosmwasm_std::{
-    to_binary, Decimal, Deps, Fraction, QueryRequest, StdResult, Uint128, WasmQuery,
+    to_binary, Deps, QueryRequest, StdResult, Uint128, WasmQuery,
 };
+use cw20::{BalanceResponse, Cw20QueryMsg};
 
+use margined_perp::margined_insurance_fund::QueryMsg as InsuranceFundQueryMsg;
 use margined_perp::margined_vamm::{
-    QueryMsg, StateResponse
+    CalcFeeResponse, ConfigResponse, Direction, QueryMsg, StateResponse,
 };
 
-// returns the state of the request vamm
-// can be used to calculate the input and outputs
+// returns the config of the vamm
+pub fn query_vamm_config(deps: &Deps, vamm_address: String) -> StdResult<ConfigResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: vamm_address,
+        msg: to_binary(&QueryMsg::Config {})?,
+    }))
+}
+
+// returns the state of the vamm
 pub fn query_vamm_state(deps: &Deps, vamm_address: String) -> StdResult<StateResponse> {
     deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
         contract_addr: vamm_address,
         msg: to_binary(&QueryMsg::State {})?,
     }))
 }
+
+// returns the length of the liquidity history of the vamm, the
+// latest change of liquidity has the index one less than this
+pub fn query_vamm_liquidity_history_length(deps: &Deps, vamm_address: String) -> StdResult<u64> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: vamm_address,
+        msg: to_binary(&QueryMsg::LiquidityHistoryLength {})?,
+    }))
+}
+
+// returns the toll and spread fees of a trade of the quote asset amount
+pub fn query_vamm_calc_fee(
+    deps: &Deps,
+    vamm_address: String,
+    quote_asset_amount: Uint128,
+) -> StdResult<CalcFeeResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: vamm_address,
+        msg: to_binary(&QueryMsg::CalcFee {
+            quote_asset_amount,
+        })?,
+    }))
+}
+
+// returns the spot price of the vamm
+pub fn query_vamm_spot_price(deps: &Deps, vamm_address: String) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: vamm_address,
+        msg: to_binary(&QueryMsg::SpotPrice {})?,
+    }))
+}
+
+// returns the index price of the underlying asset of the vamm
+pub fn query_vamm_underlying_price(deps: &Deps, vamm_address: String) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: vamm_address,
+        msg: to_binary(&QueryMsg::UnderlyingPrice {})?,
+    }))
+}
+
+// returns the twap of the vamm over the interval (in seconds)
+pub fn query_vamm_twap(deps: &Deps, vamm_address: String, interval: u64) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: vamm_address,
+        msg: to_binary(&QueryMsg::Twap {
+            interval,
+        })?,
+    }))
+}
+
+// returns the quote asset a swap of the base asset amount would return
+pub fn query_vamm_output_price(
+    deps: &Deps,
+    vamm_address: String,
+    direction: Direction,
+    base_asset_amount: Uint128,
+) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: vamm_address,
+        msg: to_binary(&QueryMsg::OutputPrice {
+            direction,
+            base_asset_amount,
+        })?,
+    }))
+}
+
+// returns the balance of the insurance fund
+pub fn query_insurance_fund_balance(
+    deps: &Deps,
+    insurance_fund: String,
+) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: insurance_fund,
+        msg: to_binary(&InsuranceFundQueryMsg::Balance {})?,
+    }))
+}
+
+// returns the balance of the cw20 token held by the address
+pub fn query_token_balance(
+    deps: &Deps,
+    token: String,
+    address: String,
+) -> StdResult<Uint128> {
+    let res: BalanceResponse = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: token,
+        msg: to_binary(&Cw20QueryMsg::Balance {
+            address,
+        })?,
+    }))?;
+
+    Ok(res.balance)
+}
@@ -1,11 +1,15 @@
-use cosmwasm_std::{Deps, StdResult, Uint128};
+use cosmwasm_std::{Deps, StdError, StdResult, Uint128};
 use margined_perp::margined_engine::{
-    ConfigResponse, PositionResponse,
+    BadDebtResponse, ConfigResponse, PNLCalc, PositionResponse,
 };
+use margined_perp::signed_decimal::SignedDecimal;
 
-use crate::state::{
-    Config, read_config,
-    read_position, read_vamm,
+use crate::{
+    handle::{calc_funding_payment, calc_margin_ratio},
+    state::{
+        Config, read_config,
+        read_position, read_vamm, read_bad_debt,
+    },
 };
 
 /// Queries contract Config
@@ -16,6 +20,11 @@ pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
         ConfigResponse {
             owner: config.owner,
             eligible_collateral: config.eligible_collateral,
+            insurance_fund: config.insurance_fund,
+            fee_pool: config.fee_pool,
+            partial_liquidation_ratio: config.partial_liquidation_ratio,
+            spread_limit_ratio: config.spread_limit_ratio,
+            bad_debt_mode: config.bad_debt_mode,
         }
     )
 }
@@ -27,7 +36,7 @@ pub fn query_position(deps: Deps, vamm: String, trader: String) -> StdResult<Pos
         deps.storage,
         &deps.api.addr_validate(&vamm)?,
         &deps.api.addr_validate(&trader)?,
-    )?.unwrap();
+    )?.ok_or_else(|| StdError::generic_err("no position found"))?;
 
     Ok(
         PositionResponse {
@@ -47,13 +56,57 @@ pub fn query_trader_balance_with_funding_payment(
     deps: Deps,
     trader: String
 ) -> StdResult<Uint128> {
+    let trader = deps.api.addr_validate(&trader)?;
+
     let mut margin = Uint128::zero();
     let vamm_list = read_vamm(deps.storage)?;
     for vamm in vamm_list.vamm.iter() {
-        let position = query_position(deps, vamm.to_string(), trader.clone())?;
-        margin = margin.checked_add(position.margin)?;
+        let position = match read_position(deps.storage, vamm, &trader)? {
+            Some(position) => position,
+            None => continue,
+        };
 
+        // funding owed beyond the margin doesn't reduce the other positions
+        let remaining_margin = SignedDecimal::from(position.margin)
+            .checked_sub(calc_funding_payment(deps, &position)?)?;
+        if remaining_margin.is_positive() {
+            margin = margin.checked_add(remaining_margin.abs())?;
+        }
     }
 
     Ok(margin)
-}
\ No newline at end of file
+}
+
+/// Queries the margin ratio of the traders position with the position valued
+/// by the calc option, zero if the trader has no position
+pub fn query_margin_ratio(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+    calc_option: PNLCalc,
+) -> StdResult<SignedDecimal> {
+    let position = read_position(
+        deps.storage,
+        &deps.api.addr_validate(&vamm)?,
+        &deps.api.addr_validate(&trader)?,
+    )?;
+
+    match position {
+        Some(position) => calc_margin_ratio(deps, &position, calc_option),
+        None => Ok(SignedDecimal::zero()),
+    }
+}
+
+/// Queries the bad debt of the vamm, and whether it has been paused because
+/// the insurance fund couldn't cover it
+pub fn query_bad_debt(deps: Deps, vamm: String) -> StdResult<BadDebtResponse> {
+    let bad_debt = read_bad_debt(deps.storage, &deps.api.addr_validate(&vamm)?)?;
+
+    Ok(
+        BadDebtResponse {
+            total: bad_debt.total,
+            socialized_loss: bad_debt.socialized_loss,
+            paused: bad_debt.paused,
+        }
+    )
+}
@@ -9,23 +9,34 @@ use cosmwasm_storage::{
 };
 use cw_storage_plus::Item;
 
-use margined_perp::margined_vamm::Direction;
+use margined_perp::margined_engine::{BadDebtMode, Side};
+use margined_perp::signed_decimal::SignedDecimal;
 
 use sha3::{Digest, Sha3_256};
 
 pub static KEY_CONFIG: &[u8] = b"config";
 pub static KEY_POSITION: &[u8] = b"position";
-pub static KEY_TMP_POSITION: &[u8] = b"tmp-position";
+pub static KEY_TMP_SWAP: &[u8] = b"tmp-swap";
+pub static KEY_TMP_LIQUIDATOR: &[u8] = b"tmp-liquidator";
+pub static KEY_TMP_EXCHANGED: &[u8] = b"tmp-exchanged";
+pub static KEY_TMP_REVERSE: &[u8] = b"tmp-reverse";
+pub static KEY_OPEN_INTEREST_NOTIONAL: &[u8] = b"open-interest-notional";
+pub static KEY_BAD_DEBT: &[u8] = b"bad-debt";
 pub const VAMM_LIST: Item<VammList> = Item::new("admin_list");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub owner: Addr,
     pub eligible_collateral: Addr,
+    pub insurance_fund: Addr, // receives the spread fees
+    pub fee_pool: Addr, // receives the toll fees
     pub decimals: Uint128,
     pub initial_margin_ratio: Uint128,
     pub maintenance_margin_ratio: Uint128,
     pub liquidation_fee: Uint128,
+    pub partial_liquidation_ratio: Uint128, // zero always liquidates in full
+    pub spread_limit_ratio: Uint128, // zero uses the spot price regardless
+    pub bad_debt_mode: BadDebtMode, // if the insurance fund can't cover bad debt
 }
 
 pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
@@ -51,7 +62,7 @@ impl VammList {
 
 pub fn store_vamm(deps: DepsMut, input: &[String]) -> StdResult<()> {
     let cfg = VammList {
-        vamm: map_validate(deps.api, &input)?,
+        vamm: map_validate(deps.api, input)?,
     };
     VAMM_LIST.save(deps.storage, &cfg)
 }
@@ -68,11 +79,10 @@ pub fn map_validate(api: &dyn Api, input: &[String]) -> StdResult<Vec<Addr>> {
 pub struct Position {
     pub vamm: Addr,
     pub trader: Addr,
-    pub direction: Direction,
-    pub size: Uint128,
+    pub size: SignedDecimal, // positive for longs, negative for shorts
     pub margin: Uint128,
     pub notional: Uint128,
-    pub premium_fraction: Uint128,
+    pub premium_fraction: SignedDecimal,
     pub liquidity_history_index: Uint128,
     pub timestamp: Timestamp,
 }
@@ -82,61 +92,151 @@ impl Default for Position {
         Position{
             vamm: Addr::unchecked(""),
             trader: Addr::unchecked(""),
-            direction: Direction::AddToAmm,
-            size: Uint128::zero(),
+            size: SignedDecimal::zero(),
             margin: Uint128::zero(),
             notional: Uint128::zero(),
-            premium_fraction: Uint128::zero(),
+            premium_fraction: SignedDecimal::zero(),
             liquidity_history_index: Uint128::zero(),
             timestamp: Timestamp::from_seconds(0),
         }
     }
 }
 
-fn position_bucket(storage: &mut dyn Storage) -> Bucket<Position> {
+fn position_bucket(storage: &mut dyn Storage) -> Bucket<'_, Position> {
     bucket(storage, KEY_POSITION)
 }
 
-fn position_bucket_read(storage: &dyn Storage) -> ReadonlyBucket<Position> {
+fn position_bucket_read(storage: &dyn Storage) -> ReadonlyBucket<'_, Position> {
     bucket_read(storage, KEY_POSITION)
 }
 
-pub fn store_position(storage: &mut dyn Storage, position: &Position) -> StdResult<()> {
-    // hash the vAMM and trader together to get a unique position key
+// hashes the vAMM and trader together to get a unique position key
+fn position_key(vamm: &Addr, trader: &Addr) -> Vec<u8> {
     let mut hasher = Sha3_256::new();
 
     // write input message
-    hasher.update(position.vamm.as_bytes());
-    hasher.update(position.trader.as_bytes());
+    hasher.update(vamm.as_bytes());
+    hasher.update(trader.as_bytes());
 
     // read hash digest
-    let hash = hasher.finalize();
+    hasher.finalize().to_vec()
+}
 
-    position_bucket(storage).save(&hash, position)
+pub fn store_position(storage: &mut dyn Storage, position: &Position) -> StdResult<()> {
+    position_bucket(storage).save(&position_key(&position.vamm, &position.trader), position)
 }
 
 pub fn read_position(storage: &dyn Storage, vamm: &Addr, trader: &Addr) -> StdResult<Option<Position>> {
-    // hash the vAMM and trader together to get a unique position key
-    let mut hasher = Sha3_256::new();
+    position_bucket_read(storage).may_load(&position_key(vamm, trader))
+}
 
-    // write input message
-    hasher.update(vamm.as_bytes());
-    hasher.update(trader.as_bytes());
+pub fn remove_position(storage: &mut dyn Storage, position: &Position) {
+    position_bucket(storage).remove(&position_key(&position.vamm, &position.trader))
+}
 
-    // read hash digest
-    let hash = hasher.finalize();
-    position_bucket_read(storage).may_load(&hash)
+/// The position and the side of the swap that is waiting on a reply from
+/// the vAMM, the side is needed as a new position has no size yet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TmpSwapInfo {
+    pub position: Position,
+    pub side: Side,
+}
+
+pub fn store_tmp_swap(storage: &mut dyn Storage, swap: &TmpSwapInfo) -> StdResult<()> {
+    singleton(storage, KEY_TMP_SWAP).save(swap)
+}
+
+pub fn remove_tmp_swap(storage: &mut dyn Storage) {
+    let mut store: Singleton<TmpSwapInfo> = singleton(storage, KEY_TMP_SWAP);
+    store.remove()
 }
 
-pub fn store_tmp_position(storage: &mut dyn Storage, position: &Position) -> StdResult<()> {
-    singleton(storage, KEY_TMP_POSITION).save(position)
+pub fn read_tmp_swap(storage: &dyn Storage) -> StdResult<Option<TmpSwapInfo>> {
+    singleton_read(storage, KEY_TMP_SWAP).may_load()
 }
 
-pub fn remove_tmp_position(storage: &mut dyn Storage) {
-    let mut store: Singleton<Position> = singleton(storage, KEY_TMP_POSITION);
+/// The trade that reverses a position, what is left of it once the position
+/// is closed opens a new one on the other side. If the margin wasn't sent
+/// with the trade it is transferred from the trader
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TmpReverseInfo {
+    pub quote_asset_amount: Uint128,
+    pub open_notional: Uint128,
+    pub leverage: Uint128,
+    pub base_asset_amount_limit: Option<Uint128>,
+    pub transfer: bool,
+}
+
+pub fn store_tmp_reverse(storage: &mut dyn Storage, reverse: &TmpReverseInfo) -> StdResult<()> {
+    singleton(storage, KEY_TMP_REVERSE).save(reverse)
+}
+
+pub fn remove_tmp_reverse(storage: &mut dyn Storage) {
+    let mut store: Singleton<TmpReverseInfo> = singleton(storage, KEY_TMP_REVERSE);
     store.remove()
 }
 
-pub fn read_tmp_position(storage: &dyn Storage) -> StdResult<Option<Position>> {
-    singleton_read(storage, KEY_TMP_POSITION).load()
+pub fn read_tmp_reverse(storage: &dyn Storage) -> StdResult<Option<TmpReverseInfo>> {
+    singleton_read(storage, KEY_TMP_REVERSE).may_load()
+}
+
+/// The liquidator of the position that is waiting on a reply from the vAMM
+pub fn store_tmp_liquidator(storage: &mut dyn Storage, liquidator: &Addr) -> StdResult<()> {
+    singleton(storage, KEY_TMP_LIQUIDATOR).save(liquidator)
+}
+
+pub fn remove_tmp_liquidator(storage: &mut dyn Storage) {
+    let mut store: Singleton<Addr> = singleton(storage, KEY_TMP_LIQUIDATOR);
+    store.remove()
+}
+
+pub fn read_tmp_liquidator(storage: &dyn Storage) -> StdResult<Option<Addr>> {
+    singleton_read(storage, KEY_TMP_LIQUIDATOR).may_load()
+}
+
+/// The quote asset already exchanged for a liquidation that closes the
+/// position in more than one swap
+pub fn store_tmp_exchanged(storage: &mut dyn Storage, exchanged: &Uint128) -> StdResult<()> {
+    singleton(storage, KEY_TMP_EXCHANGED).save(exchanged)
+}
+
+pub fn remove_tmp_exchanged(storage: &mut dyn Storage) {
+    let mut store: Singleton<Uint128> = singleton(storage, KEY_TMP_EXCHANGED);
+    store.remove()
+}
+
+// returns zero if nothing has been exchanged yet
+pub fn read_tmp_exchanged(storage: &dyn Storage) -> StdResult<Uint128> {
+    Ok(singleton_read(storage, KEY_TMP_EXCHANGED)
+        .may_load()?
+        .unwrap_or_default())
+}
+
+pub fn store_open_interest_notional(storage: &mut dyn Storage, vamm: &Addr, notional: &Uint128) -> StdResult<()> {
+    bucket(storage, KEY_OPEN_INTEREST_NOTIONAL).save(vamm.as_bytes(), notional)
+}
+
+// returns the total open notional of the vamm, zero if nothing has been opened
+pub fn read_open_interest_notional(storage: &dyn Storage, vamm: &Addr) -> StdResult<Uint128> {
+    Ok(bucket_read(storage, KEY_OPEN_INTEREST_NOTIONAL)
+        .may_load(vamm.as_bytes())?
+        .unwrap_or_default())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct BadDebt {
+    pub total: Uint128, // all the bad debt the vamm has realized
+    pub socialized_loss: Uint128, // still to be taken pro rata from profitable positions
+    pub paused: bool, // set when the insurance fund couldn't cover bad debt
+}
+
+pub fn store_bad_debt(storage: &mut dyn Storage, vamm: &Addr, bad_debt: &BadDebt) -> StdResult<()> {
+    bucket(storage, KEY_BAD_DEBT).save(vamm.as_bytes(), bad_debt)
+}
+
+// returns the bad debt of the vamm, the default if it has never had any
+pub fn read_bad_debt(storage: &dyn Storage, vamm: &Addr) -> StdResult<BadDebt> {
+    Ok(bucket_read(storage, KEY_BAD_DEBT)
+        .may_load(vamm.as_bytes())?
+        .unwrap_or_default())
 }
@@ -1,10 +1,19 @@
 use cw20::{Cw20Contract, Cw20ExecuteMsg};
 use cw_multi_test::{Executor};
-use cosmwasm_std::{to_binary, Uint128};
+use cosmwasm_std::{to_binary, StdResult, Uint128};
 use margined_perp::margined_engine::{
-    ConfigResponse, Cw20HookMsg, QueryMsg, Side, ExecuteMsg,
+    BadDebtMode, BadDebtResponse, ConfigResponse, Cw20HookMsg, PNLCalc, QueryMsg,
+    Side, ExecuteMsg,
     PositionResponse,
 };
+use margined_perp::margined_insurance_fund::{
+    DeficitResponse, QueryMsg as InsuranceFundQueryMsg,
+};
+use margined_perp::margined_pricefeed::ExecuteMsg as PricefeedExecuteMsg;
+use margined_perp::margined_vamm::{
+    ExecuteMsg as VammExecuteMsg, QueryMsg as VammQueryMsg, StateResponse,
+};
+use margined_perp::signed_decimal::SignedDecimal;
 use crate::testing::setup::{
     self, to_decimals,
 };
@@ -39,6 +48,7 @@ fn test_open_position_long() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(60u64),
         leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
     };
 
     let _res = env.router.execute_contract(
@@ -65,7 +75,7 @@ fn test_open_position_long() {
             trader: env.alice.to_string(),
         })
         .unwrap();
-    assert_eq!(Uint128::new(37500_000_000), position.size);
+    assert_eq!(SignedDecimal::from(Uint128::new(37_500_000_000)), position.size);
     assert_eq!(to_decimals(60u64), position.margin);
 
     // clearing house token balance should be 60
@@ -75,53 +85,42 @@ fn test_open_position_long() {
 }
 
 #[test]
-fn test_open_position_two_longs() {
+fn test_open_position_long_below_limit() {
     let mut env = setup::setup();
 
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(60u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::BUY,
-            leverage: to_decimals(10u64),
-        }).unwrap(),
+    // position would be 37.5, so asking for at least 40 should fail
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: Some(to_decimals(40u64)),
     };
 
-    let _res = env.router.execute_contract(
+    let res = env.router.execute_contract(
         env.alice.clone(),
-        env.usdc.addr.clone(),
-        &send_msg,
+        env.engine.addr.clone(),
+        &msg,
         &[]
-    ).unwrap();
+    );
+    assert!(res.is_err());
 
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(60u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::BUY,
-            leverage: to_decimals(10u64),
-        }).unwrap(),
+    // and with a limit of 37.5 it should go through
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: Some(Uint128::new(37_500_000_000)),
     };
 
     let _res = env.router.execute_contract(
         env.alice.clone(),
-        env.usdc.addr.clone(),
-        &send_msg,
+        env.engine.addr.clone(),
+        &msg,
         &[]
     ).unwrap();
 
-    // expect to be 120
-    let margin = env.router
-    .wrap()
-    .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
-        trader: env.alice.to_string(),
-    })
-    .unwrap();
-    assert_eq!(to_decimals(120), margin);
-
-    // retrieve the vamm state
     let position: PositionResponse = env.router
         .wrap()
         .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
@@ -129,65 +128,62 @@ fn test_open_position_two_longs() {
             trader: env.alice.to_string(),
         })
         .unwrap();
-    assert_eq!(Uint128::new(54_545_454_545), position.size);
-    assert_eq!(to_decimals(120), position.margin);
-
+    assert_eq!(SignedDecimal::from(Uint128::new(37_500_000_000)), position.size);
 }
 
 #[test]
-fn test_open_position_two_shorts() {
+fn test_reverse_position_over_limit() {
     let mut env = setup::setup();
 
-    // verify the engine owner
-    let _config: ConfigResponse = env.router
-        .wrap()
-        .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
-        .unwrap();
-
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(40u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::SELL,
-            leverage: to_decimals(5u64),
-        }).unwrap(),
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
     };
 
     let _res = env.router.execute_contract(
         env.alice.clone(),
-        env.usdc.addr.clone(),
-        &send_msg,
+        env.engine.addr.clone(),
+        &msg,
         &[]
     ).unwrap();
 
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(40u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::SELL,
-            leverage: to_decimals(5u64),
-        }).unwrap(),
+    // closing the long takes 600 of the trade and the short opened with the
+    // other 600 would be 150, so paying at most 140 should fail
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(120u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: Some(to_decimals(140u64)),
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    );
+    assert!(res.is_err());
+
+    // and with a limit of 150 it should go through
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(120u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: Some(to_decimals(150u64)),
     };
 
     let _res = env.router.execute_contract(
         env.alice.clone(),
-        env.usdc.addr.clone(),
-        &send_msg,
+        env.engine.addr.clone(),
+        &msg,
         &[]
     ).unwrap();
 
-    // personal balance with funding payment
-    let margin = env.router
-        .wrap()
-        .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
-            trader: env.alice.to_string(),
-        })
-        .unwrap();
-    assert_eq!(to_decimals(80), margin);
-
-    // retrieve the vamm state
     let position: PositionResponse = env.router
         .wrap()
         .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
@@ -195,253 +191,2899 @@ fn test_open_position_two_shorts() {
             trader: env.alice.to_string(),
         })
         .unwrap();
-    assert_eq!(Uint128::new(66_666_666_667), position.size);
-    assert_eq!(to_decimals(80), position.margin);
-
+    assert_eq!(SignedDecimal::new(to_decimals(150u64), true), position.size);
 }
 
 #[test]
-fn test_open_position_equal_size_long_short() {
+fn test_open_position_after_adjust_k() {
     let mut env = setup::setup();
 
-    // verify the engine owner
-    let _config: ConfigResponse = env.router
-        .wrap()
-        .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
-        .unwrap();
-
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(60u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::BUY,
-            leverage: to_decimals(10u64),
-        }).unwrap(),
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
     };
 
     let _res = env.router.execute_contract(
         env.alice.clone(),
-        env.usdc.addr.clone(),
-        &send_msg,
+        env.engine.addr.clone(),
+        &msg,
         &[]
     ).unwrap();
 
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(300u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::SELL,
-            leverage: to_decimals(2u64),
-        }).unwrap(),
-    };
+    // the owner doubles the liquidity of the vamm
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::AdjustK {
+            multiplier: to_decimals(2u64),
+        },
+        &[]
+    ).unwrap();
 
     let _res = env.router.execute_contract(
-        env.alice.clone(),
-        env.usdc.addr.clone(),
-        &send_msg,
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
         &[]
     ).unwrap();
 
-    // personal balance with funding payment
-    let margin = env.router
+    // alice opened under the initial liquidity and bob under the new
+    let position: PositionResponse = env.router
         .wrap()
-        .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
             trader: env.alice.to_string(),
         })
         .unwrap();
-    assert_eq!(Uint128::zero(), margin);
+    assert_eq!(Uint128::zero(), position.liquidity_history_index);
 
-    // retrieve the vamm state
     let position: PositionResponse = env.router
         .wrap()
         .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
             vamm: env.vamm.addr.to_string(),
-            trader: env.alice.to_string(),
+            trader: env.bob.to_string(),
         })
         .unwrap();
-    assert_eq!(Uint128::zero(), position.size);
-    assert_eq!(Uint128::zero(), position.margin);
-
+    assert_eq!(Uint128::new(1), position.liquidity_history_index);
 }
 
 #[test]
-fn test_open_position_one_long_two_shorts() {
+fn test_close_position_long_with_profit() {
     let mut env = setup::setup();
 
-    // verify the engine owner
-    let _config: ConfigResponse = env.router
-        .wrap()
-        .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
-        .unwrap();
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
 
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(60u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::BUY,
-            leverage: to_decimals(10u64),
-        }).unwrap(),
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
     };
 
     let _res = env.router.execute_contract(
         env.alice.clone(),
-        env.usdc.addr.clone(),
-        &send_msg,
+        env.engine.addr.clone(),
+        &msg,
         &[]
     ).unwrap();
 
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(20u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::SELL,
-            leverage: to_decimals(5u64),
-        }).unwrap(),
-    };
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
 
+    // the insurance fund has 500 to pay out profits the engine can't cover
     let _res = env.router.execute_contract(
-        env.alice.clone(),
+        env.bob.clone(),
         env.usdc.addr.clone(),
-        &send_msg,
+        &Cw20ExecuteMsg::Transfer {
+            recipient: env.insurance_fund.addr.to_string(),
+            amount: to_decimals(500u64),
+        },
         &[]
     ).unwrap();
 
-    // retrieve the vamm state
-    let position: PositionResponse = env.router
-        .wrap()
-        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
-            vamm: env.vamm.addr.to_string(),
-            trader: env.alice.to_string(),
-        })
-        .unwrap();
-    assert_eq!(Uint128::new(33_333_333_333), position.size);
-    assert_eq!(to_decimals(60), position.margin);
-
-    let send_msg = Cw20ExecuteMsg::Send {
-        contract: env.engine.addr.to_string(),
-        amount: to_decimals(50u64),
-        msg: to_binary(&Cw20HookMsg::OpenPosition {
-            vamm: env.vamm.addr.to_string(),
-            side: Side::SELL,
-            leverage: to_decimals(10u64),
-        }).unwrap(),
+    // bob's long pushes the price up so alice closes in profit
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        quote_asset_amount_limit: None,
     };
 
-    let _res = env.router.execute_contract(
+    let res = env.router.execute_contract(
         env.alice.clone(),
-        env.usdc.addr.clone(),
-        &send_msg,
+        env.engine.addr.clone(),
+        &msg,
         &[]
     ).unwrap();
 
-    // personal balance with funding payment
-    let margin = env.router
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    // 37.5 base is sold back for 2200 - 100000 / 82.9545... = 994.52...
+    assert_eq!(realized_pnl.value, "394520547939");
+
+    // alice gets back her margin of 60 and the profit, the engine only holds
+    // the 120 margin of alice and bob so the insurance fund pays the rest
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::new(5_394_520_547_939));
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, Uint128::zero());
+    let insurance_fund_balance = usdc.balance(&env.router, env.insurance_fund.addr.clone()).unwrap();
+    assert_eq!(insurance_fund_balance, Uint128::new(165_479_452_061));
+
+    // which isn't bad debt so the fund has no deficit
+    let deficits: Vec<DeficitResponse> = env.router
         .wrap()
-        .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
-            trader: env.alice.to_string(),
+        .query_wasm_smart(&env.insurance_fund.addr, &InsuranceFundQueryMsg::DeficitHistory {
+            start_after: None,
+            limit: None,
         })
         .unwrap();
-    assert_eq!(Uint128::zero(), margin);
+    assert!(deficits.is_empty());
 
-    // retrieve the vamm state
-    let position: PositionResponse = env.router
+    // and the position is deleted
+    let res: StdResult<PositionResponse> = env.router
         .wrap()
         .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
             vamm: env.vamm.addr.to_string(),
             trader: env.alice.to_string(),
-        })
-        .unwrap();
-    assert_eq!(Uint128::zero(), position.size);
-    assert_eq!(Uint128::zero(), position.margin);
-    
+        });
+    assert!(res.is_err());
 }
 
-// #[test]
-// fn test_open_position_short_and_two_longs() {
-//     let mut env = setup::setup();
+#[test]
+fn test_close_position_short_with_profit() {
+    let mut env = setup::setup();
 
-//     // verify the engine owner
-//     let _config: ConfigResponse = env.router
-//         .wrap()
-//         .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
-//         .unwrap();
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
 
-//     let send_msg = Cw20ExecuteMsg::Send {
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(10u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // the insurance fund has 500 to pay out profits the engine can't cover
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.usdc.addr.clone(),
+        &Cw20ExecuteMsg::Transfer {
+            recipient: env.insurance_fund.addr.to_string(),
+            amount: to_decimals(500u64),
+        },
+        &[]
+    ).unwrap();
+
+    // bob's short pushes the price down so alice closes in profit
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        quote_asset_amount_limit: None,
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    // 150 base is bought back for 100000 / 183.33... - 300 = 245.45...
+    assert_eq!(realized_pnl.value, "354545454546");
+
+    // alice gets back her margin of 60 and the profit, the engine only holds
+    // the 70 margin of alice and bob so the insurance fund pays the rest
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::new(5_354_545_454_546));
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, Uint128::zero());
+
+    let res: StdResult<PositionResponse> = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        });
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_close_position_long_with_loss() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(10u64),
+        leverage: to_decimals(5u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // bob's short pushes the price down so alice closes at a loss
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        quote_asset_amount_limit: None,
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    // 37.5 base is sold back for 1550 - 100000 / 102.01... = 569.76...
+    assert_eq!(realized_pnl.value, "-30237154155");
+
+    // alice gets back what is left of her margin after the loss
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::new(4_969_762_845_845));
+    // and the loss stays with the engine along with bob's margin
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, Uint128::new(40_237_154_155));
+
+    let res: StdResult<PositionResponse> = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        });
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_close_position_with_funding_owed() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // the spot price of 25.6 is over the oracle price of 10 so longs pay
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(3_600);
+        block.height += 1;
+    });
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::PayFunding {
+            vamm: env.vamm.addr.to_string(),
+        },
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        quote_asset_amount_limit: None,
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let funding_payment = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "funding_payment")
+        .unwrap();
+    // the long pays 0.65 on its size of 37.5
+    assert_eq!(funding_payment.value, "24375000000");
+
+    // the position closes for what it was opened for so alice gets back
+    // her margin less the funding, 60 - 24.375 = 35.625
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::new(4_975_625_000_000));
+    // alice is the only position so all of the funding she pays is
+    // imbalance and went to the insurance fund when it was settled
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, Uint128::zero());
+    let insurance_fund_balance = usdc.balance(&env.router, env.insurance_fund.addr.clone()).unwrap();
+    assert_eq!(insurance_fund_balance, Uint128::new(24_375_000_000));
+}
+
+#[test]
+fn test_open_position_over_max_holding() {
+    let mut env = setup::setup();
+
+    // traders can hold at most 30 of the base asset
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::UpdateConfig {
+            owner: None,
+            margin_engine: None,
+            pricefeed: None,
+            fluctuation_limit_ratio: None,
+            max_holding_base_asset: Some(to_decimals(30u64)),
+            open_interest_notional_cap: None,
+            toll_ratio: None,
+            spread_ratio: None,
+        },
+        &[]
+    ).unwrap();
+
+    // position would be 37.5
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    );
+    assert!(res.is_err());
+
+    // position is 100 - 100000 / 1300 = 23.07...
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(30u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // adding 100000 / 1300 - 100000 / 1500 = 10.25... takes it over
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(20u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    );
+    assert!(res.is_err());
+
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(SignedDecimal::from(Uint128::new(23_076_923_076)), position.size);
+}
+
+#[test]
+fn test_open_position_over_open_interest_cap() {
+    let mut env = setup::setup();
+
+    // at most 1000 notional can be open on the vamm
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::UpdateConfig {
+            owner: None,
+            margin_engine: None,
+            pricefeed: None,
+            fluctuation_limit_ratio: None,
+            max_holding_base_asset: None,
+            open_interest_notional_cap: Some(to_decimals(1_000u64)),
+            toll_ratio: None,
+            spread_ratio: None,
+        },
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // a further 600 takes the open interest to 1200
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    );
+    assert!(res.is_err());
+
+    // once alice closes there is room for bob
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::ClosePosition {
+            vamm: env.vamm.addr.to_string(),
+            quote_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+}
+
+#[test]
+fn test_open_interest_after_reducing_position() {
+    let mut env = setup::setup();
+
+    // at most 1000 notional can be open on the vamm
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::UpdateConfig {
+            owner: None,
+            margin_engine: None,
+            pricefeed: None,
+            fluctuation_limit_ratio: None,
+            max_holding_base_asset: None,
+            open_interest_notional_cap: Some(to_decimals(1_000u64)),
+            toll_ratio: None,
+            spread_ratio: None,
+        },
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            quote_asset_amount: to_decimals(60u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    // the short closes 8.92... of the long which is 142.85... of the entry
+    // notional, so 457.14... of open interest is left
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            quote_asset_amount: to_decimals(20u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    // a further 570 takes the open interest over 1000
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            quote_asset_amount: to_decimals(57u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    );
+    assert!(res.is_err());
+
+    // closing what is left of the position clears all of the open interest
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::ClosePosition {
+            vamm: env.vamm.addr.to_string(),
+            quote_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            quote_asset_amount: to_decimals(100u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+}
+
+#[test]
+fn test_open_position_two_longs() {
+    let mut env = setup::setup();
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(60u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(60u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    // expect to be 120
+    let margin = env.router
+    .wrap()
+    .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
+        trader: env.alice.to_string(),
+    })
+    .unwrap();
+    assert_eq!(to_decimals(120), margin);
+
+    // retrieve the vamm state
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(SignedDecimal::from(Uint128::new(54_545_454_545)), position.size);
+    assert_eq!(to_decimals(120), position.margin);
+
+}
+
+#[test]
+fn test_open_position_two_shorts() {
+    let mut env = setup::setup();
+
+    // verify the engine owner
+    let _config: ConfigResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
+        .unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(40u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            leverage: to_decimals(5u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(40u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            leverage: to_decimals(5u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    // personal balance with funding payment
+    let margin = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(to_decimals(80), margin);
+
+    // retrieve the vamm state
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(-SignedDecimal::from(Uint128::new(66_666_666_667)), position.size);
+    assert_eq!(to_decimals(80), position.margin);
+
+}
+
+#[test]
+fn test_open_position_equal_size_long_short() {
+    let mut env = setup::setup();
+
+    // verify the engine owner
+    let _config: ConfigResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
+        .unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(60u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(300u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            leverage: to_decimals(2u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    // personal balance with funding payment
+    let margin = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(Uint128::zero(), margin);
+
+    // the position is closed so there is nothing left of it
+    let res = env.router
+        .wrap()
+        .query_wasm_smart::<PositionResponse>(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        });
+    assert!(res.is_err());
+
+}
+
+#[test]
+fn test_open_position_one_long_two_shorts() {
+    let mut env = setup::setup();
+
+    // verify the engine owner
+    let _config: ConfigResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
+        .unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(60u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(20u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            leverage: to_decimals(5u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    // retrieve the vamm state
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(SignedDecimal::from(Uint128::new(33_333_333_333)), position.size);
+    // the short closes 4.16... of the long for 100 which realizes 33.33... profit
+    assert_eq!(Uint128::new(93_333_333_328), position.margin);
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(50u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    // personal balance with funding payment
+    let margin = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(Uint128::zero(), margin);
+
+    // the position is closed so there is nothing left of it
+    let res = env.router
+        .wrap()
+        .query_wasm_smart::<PositionResponse>(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        });
+    assert!(res.is_err());
+    
+}
+
+#[test]
+fn test_open_position_with_hook_pays_margin_once() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(60u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    // the margin sent with the hook is all that is taken from alice
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_940));
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, to_decimals(60));
+
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(position.margin, to_decimals(60));
+
+    // reducing the position takes no margin so what is sent is returned
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(20u64),
+        msg: to_binary(&Cw20HookMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            leverage: to_decimals(5u64),
+            base_asset_amount_limit: None,
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_940));
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, to_decimals(60));
+}
+
+// #[test]
+// fn test_open_position_short_and_two_longs() {
+//     let mut env = setup::setup();
+
+//     // verify the engine owner
+//     let _config: ConfigResponse = env.router
+//         .wrap()
+//         .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
+//         .unwrap();
+
+//     let send_msg = Cw20ExecuteMsg::Send {
 //         contract: env.engine.addr.to_string(),
 //         amount: to_decimals(40u64),
 //         msg: to_binary(&Cw20HookMsg::OpenPosition {
 //             vamm: env.vamm.addr.to_string(),
 //             side: Side::SELL,
 //             leverage: to_decimals(5u64),
+//             base_asset_amount_limit: None,
+//         }).unwrap(),
+//     };
+
+//     let _res = env.router.execute_contract(
+//         env.alice.clone(),
+//         env.usdc.addr.clone(),
+//         &send_msg,
+//         &[]
+//     ).unwrap();
+
+//     let send_msg = Cw20ExecuteMsg::Send {
+//         contract: env.engine.addr.to_string(),
+//         amount: to_decimals(20u64),
+//         msg: to_binary(&Cw20HookMsg::OpenPosition {
+//             vamm: env.vamm.addr.to_string(),
+//             side: Side::BUY,
+//             leverage: to_decimals(5u64),
+//             base_asset_amount_limit: None,
+//         }).unwrap(),
+//     };
+
+//     let _res = env.router.execute_contract(
+//         env.alice.clone(),
+//         env.usdc.addr.clone(),
+//         &send_msg,
+//         &[]
+//     ).unwrap();
+
+//         // retrieve the vamm state
+//     let position: PositionResponse = env.router
+//         .wrap()
+//         .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+//             vamm: env.vamm.addr.to_string(),
+//             trader: env.alice.to_string(),
+//         })
+//         .unwrap();
+//     assert_eq!(SignedDecimal::from(Uint128::new(11_111_111_112)), position.size);
+//     assert_eq!(to_decimals(40), position.margin);
+
+//     let send_msg = Cw20ExecuteMsg::Send {
+//         contract: env.engine.addr.to_string(),
+//         amount: to_decimals(20u64),
+//         msg: to_binary(&Cw20HookMsg::OpenPosition {
+//             vamm: env.vamm.addr.to_string(),
+//             side: Side::BUY,
+//             leverage: to_decimals(5u64),
+//             base_asset_amount_limit: None,
 //         }).unwrap(),
 //     };
 
-//     let _res = env.router.execute_contract(
-//         env.alice.clone(),
-//         env.usdc.addr.clone(),
-//         &send_msg,
-//         &[]
-//     ).unwrap();
+//     let _res = env.router.execute_contract(
+//         env.alice.clone(),
+//         env.usdc.addr.clone(),
+//         &send_msg,
+//         &[]
+//     ).unwrap();
+
+
+//     // retrieve the vamm state
+//     let position: PositionResponse = env.router
+//         .wrap()
+//         .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+//             vamm: env.vamm.addr.to_string(),
+//             trader: env.alice.to_string(),
+//         })
+//         .unwrap();
+//     assert_eq!(SignedDecimal::from(Uint128::new(1)), position.size);
+//     assert_eq!(Uint128::new(1), position.margin);
+
+// }
+
+#[test]
+fn test_settle_position_after_shutdown() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(1u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // positions can't be settled while the market is open
+    let settle_msg = ExecuteMsg::SettlePosition {
+        vamm: env.vamm.addr.to_string(),
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &settle_msg,
+        &[]
+    );
+    assert!(res.is_err());
+
+    // the net long of 10.71... was opened for 120, so it settles at 11.2
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::Shutdown {},
+        &[]
+    ).unwrap();
+
+    // no new positions can be opened
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    );
+    assert!(res.is_err());
+
+    // alice bought 5.66... first so settles in profit
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &settle_msg,
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    assert_eq!(realized_pnl.value, "3396226409");
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::new(5_003_396_226_409));
+
+    // bob bought 5.05... after alice so settles at a loss
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &settle_msg,
+        &[]
+    ).unwrap();
+
+    let bob_balance = usdc.balance(&env.router, env.bob.clone()).unwrap();
+    assert_eq!(bob_balance, Uint128::new(4_996_603_773_576));
+
+    // the position is deleted
+    let res: StdResult<PositionResponse> = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        });
+    assert!(res.is_err());
+
+    // a position can only be settled once
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &settle_msg,
+        &[]
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_settle_position_with_insurance_fund_top_up() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    for trader in [env.alice.clone(), env.bob.clone()] {
+        let _res = env.router.execute_contract(
+            trader,
+            env.engine.addr.clone(),
+            &msg,
+            &[]
+        ).unwrap();
+    }
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.usdc.addr.clone(),
+        &Cw20ExecuteMsg::Transfer {
+            recipient: env.insurance_fund.addr.to_string(),
+            amount: to_decimals(500u64),
+        },
+        &[]
+    ).unwrap();
+
+    // the net long of 54.54... was opened for 1200, so it settles at 22
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::Shutdown {},
+        &[]
+    ).unwrap();
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::SettlePosition {
+            vamm: env.vamm.addr.to_string(),
+        },
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    // alice's 37.5 settles for 825
+    assert_eq!(realized_pnl.value, "225000000000");
+
+    // the engine only holds the margin of 120 so the insurance fund pays the
+    // other 165 of alice's margin and profit
+    let alice_change = usdc.balance(&env.router, env.alice.clone()).unwrap() - alice_balance;
+    assert_eq!(alice_change, to_decimals(285u64));
+
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, Uint128::zero());
+    let insurance_fund_balance = usdc.balance(&env.router, env.insurance_fund.addr.clone()).unwrap();
+    assert_eq!(insurance_fund_balance, to_decimals(335u64));
+}
+
+#[test]
+fn test_settle_position_needs_shutdown() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(1u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // closing the market only pauses it, there is no settlement price
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::SetOpen {
+            open: false,
+        },
+        &[]
+    ).unwrap();
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::SettlePosition {
+            vamm: env.vamm.addr.to_string(),
+        },
+        &[]
+    );
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "Generic error: vAMM is not shut down",
+    );
+}
+
+#[test]
+fn test_open_and_close_position_with_fees() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    // a toll of 1% and spread of 0.5%
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::UpdateConfig {
+            owner: None,
+            margin_engine: None,
+            pricefeed: None,
+            fluctuation_limit_ratio: None,
+            max_holding_base_asset: None,
+            open_interest_notional_cap: None,
+            toll_ratio: Some(Uint128::new(10_000_000)),
+            spread_ratio: Some(Uint128::new(5_000_000)),
+        },
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // fees on the notional of 600 are 6 and 3
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_931));
+    let fee_pool_balance = usdc.balance(&env.router, "fee_pool").unwrap();
+    assert_eq!(fee_pool_balance, to_decimals(6));
+    let insurance_fund_balance = usdc.balance(&env.router, env.insurance_fund.addr.clone()).unwrap();
+    assert_eq!(insurance_fund_balance, to_decimals(3));
+
+    // closing for 600 pays the same fees again
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        quote_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // alice gets back her margin of 60 less the fees of 9
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_982));
+    let fee_pool_balance = usdc.balance(&env.router, "fee_pool").unwrap();
+    assert_eq!(fee_pool_balance, to_decimals(12));
+    let insurance_fund_balance = usdc.balance(&env.router, env.insurance_fund.addr.clone()).unwrap();
+    assert_eq!(insurance_fund_balance, to_decimals(6));
+}
+
+#[test]
+fn test_close_position_with_loss_over_margin_pays_no_fees() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    // a toll of 1% and spread of 0.5%
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::UpdateConfig {
+            owner: None,
+            margin_engine: None,
+            pricefeed: None,
+            fluctuation_limit_ratio: None,
+            max_holding_base_asset: None,
+            open_interest_notional_cap: None,
+            toll_ratio: Some(Uint128::new(10_000_000)),
+            spread_ratio: Some(Uint128::new(5_000_000)),
+        },
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            quote_asset_amount: to_decimals(60u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    // bob's short pushes the price down so alice loses more than her margin
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            quote_asset_amount: to_decimals(100u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    let fee_pool_balance = usdc.balance(&env.router, "fee_pool").unwrap();
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::ClosePosition {
+            vamm: env.vamm.addr.to_string(),
+            quote_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    // 37.5 base is sold back for 600 - 100000 / 204.16... = 110.20...
+    assert_eq!(realized_pnl.value, "-489795918368");
+
+    // there is no margin left to pay the fees of the close
+    assert_eq!(usdc.balance(&env.router, env.alice.clone()).unwrap(), alice_balance);
+    assert_eq!(usdc.balance(&env.router, "fee_pool").unwrap(), fee_pool_balance);
+}
+
+#[test]
+fn test_close_position_with_profit_over_available_balance() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // bob's long puts alice's long in profit
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+
+    // the insurance fund is empty so alice can only be paid what the engine holds
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::ClosePosition {
+            vamm: env.vamm.addr.to_string(),
+            quote_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let withdrawn_amount = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "withdrawn_amount")
+        .unwrap();
+    assert_eq!(withdrawn_amount.value, to_decimals(120u64).to_string());
+
+    let alice_change = usdc.balance(&env.router, env.alice.clone()).unwrap() - alice_balance;
+    assert_eq!(alice_change, to_decimals(120u64));
+
+    // what alice isn't paid is bad debt
+    let bad_debt: BadDebtResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::BadDebt {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap();
+    // alice is owed her margin of 60 and profit of 394.52...
+    assert_eq!(bad_debt.total, Uint128::new(334_520_547_939));
+    assert!(bad_debt.paused);
+}
+
+#[test]
+fn test_open_position_over_spread_limit_uses_oracle() {
+    let mut env = setup::setup();
+
+    // alice's long of 37.5 moves the spot price from 10 to 25.6
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // a spread limit of 10%
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::UpdateConfig {
+            owner: None,
+            partial_liquidation_ratio: None,
+            spread_limit_ratio: Some(Uint128::new(100_000_000)),
+            bad_debt_mode: None,
+        },
+        &[]
+    ).unwrap();
+
+    // at the oracle price of 10 the position is worth 375, so the loss of
+    // 225 is more than the margin and it can't be traded
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10u64),
+        leverage: to_decimals(1u64),
+        base_asset_amount_limit: None,
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    );
+    assert_eq!(res.unwrap_err().to_string(), "Generic error: position is undercollateralized");
+
+    // once the oracle is within the limit the spot price is used again
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+    let timestamp = env.router.block_info().time.seconds();
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.pricefeed.addr.clone(),
+        &PricefeedExecuteMsg::AppendPrice {
+            key: "USD".to_string(),
+            price: to_decimals(25u64),
+            timestamp,
+        },
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+}
+
+#[test]
+fn test_liquidate_position() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // alice's position is healthy so can't be liquidated
+    let liquidate_msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+    };
+
+    let res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &liquidate_msg,
+        &[]
+    );
+    assert_eq!(res.unwrap_err().to_string(), "Generic error: position is not undercollateralized");
+
+    // bob's short drops alice's margin ratio to 4.2%, below the 5% maintenance
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(1u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let owner_balance = usdc.balance(&env.router, env.owner.clone()).unwrap();
+
+    let res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &liquidate_msg,
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    assert_eq!(realized_pnl.value, "-36228209197");
+
+    // the liquidator is paid 2.5% of the notional of 563.77...
+    let liquidator_fee = usdc.balance(&env.router, env.owner.clone()).unwrap() - owner_balance;
+    assert_eq!(liquidator_fee, Uint128::new(14_094_294_770));
+
+    // and the margin that is left goes to the insurance fund
+    let insurance_fund_balance = usdc.balance(&env.router, env.insurance_fund.addr.clone()).unwrap();
+    assert_eq!(insurance_fund_balance, Uint128::new(9_677_496_033));
+
+    // the position is deleted
+    let res: StdResult<PositionResponse> = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        });
+    assert!(res.is_err());
+
+    // the position is closed so there is nothing left to liquidate
+    let res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &liquidate_msg,
+        &[]
+    );
+    assert_eq!(res.unwrap_err().to_string(), "Generic error: no position to liquidate");
+}
+
+#[test]
+fn test_liquidate_position_with_shortfall() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // bob's short is large enough that alice's loss is more than her margin
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(100u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // the insurance fund has 500 to cover bad debt
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.usdc.addr.clone(),
+        &Cw20ExecuteMsg::Transfer {
+            recipient: env.insurance_fund.addr.to_string(),
+            amount: to_decimals(500u64),
+        },
+        &[]
+    ).unwrap();
+
+    // alice is too far underwater for a partial liquidation
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::UpdateConfig {
+            owner: None,
+            partial_liquidation_ratio: Some(Uint128::new(250_000_000)),
+            spread_limit_ratio: None,
+            bad_debt_mode: None,
+        },
+        &[]
+    ).unwrap();
+
+    let owner_balance = usdc.balance(&env.router, env.owner.clone()).unwrap();
+
+    let res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::Liquidate {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        },
+        &[]
+    ).unwrap();
+
+    let shortfall = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "shortfall")
+        .unwrap();
+    assert_eq!(shortfall.value, "432551020408");
+
+    // the liquidator is still paid 2.5% of the notional of 110.20...
+    let liquidator_fee = usdc.balance(&env.router, env.owner.clone()).unwrap() - owner_balance;
+    assert_eq!(liquidator_fee, Uint128::new(2_755_102_040));
+
+    // and the insurance fund covers the loss beyond alice's margin
+    let insurance_fund_balance: Uint128 = env.router
+        .wrap()
+        .query_wasm_smart(&env.insurance_fund.addr, &InsuranceFundQueryMsg::Balance {})
+        .unwrap();
+    assert_eq!(insurance_fund_balance, Uint128::new(67_448_979_592));
+
+    let deficits: Vec<DeficitResponse> = env.router
+        .wrap()
+        .query_wasm_smart(&env.insurance_fund.addr, &InsuranceFundQueryMsg::DeficitHistory {
+            start_after: None,
+            limit: None,
+        })
+        .unwrap();
+    assert_eq!(
+        deficits,
+        vec![DeficitResponse {
+            index: 0,
+            amount: Uint128::new(432_551_020_408),
+            withdrawn: Uint128::new(432_551_020_408),
+            timestamp: env.router.block_info().time,
+        }],
+    );
+
+    // the bad debt is booked to the vamm, which stays open as it was covered
+    let bad_debt: BadDebtResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::BadDebt {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap();
+    assert_eq!(
+        bad_debt,
+        BadDebtResponse {
+            total: Uint128::new(432_551_020_408),
+            socialized_loss: Uint128::zero(),
+            paused: false,
+        },
+    );
+}
+
+#[test]
+fn test_partial_liquidate_position() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    // a partial liquidation ratio of 25%
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::UpdateConfig {
+            owner: None,
+            partial_liquidation_ratio: Some(Uint128::new(250_000_000)),
+            spread_limit_ratio: None,
+            bad_debt_mode: None,
+        },
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // alice's margin ratio of 4.2% is below maintenance but above the fee
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(1u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let owner_balance = usdc.balance(&env.router, env.owner.clone()).unwrap();
+
+    let res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::Liquidate {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        },
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    assert_eq!(realized_pnl.value, "-9057052299");
+
+    // the liquidator is paid 2.5% of the notional of the quarter that closed
+    let liquidator_fee = usdc.balance(&env.router, env.owner.clone()).unwrap() - owner_balance;
+    assert_eq!(liquidator_fee, Uint128::new(4_857_181_867));
+
+    // nothing goes to the insurance fund as the position is still open
+    let insurance_fund_balance = usdc.balance(&env.router, env.insurance_fund.addr.clone()).unwrap();
+    assert_eq!(insurance_fund_balance, Uint128::zero());
+
+    // three quarters of the position is left with the remaining margin
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(position.size, SignedDecimal::from(Uint128::new(28_125_000_000)));
+    assert_eq!(position.notional, to_decimals(450u64));
+    assert_eq!(position.margin, Uint128::new(46_085_765_834));
+}
+
+#[test]
+fn test_query_margin_ratio() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // at the spot price the position closes for what it was opened for
+    let margin_ratio: SignedDecimal = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::MarginRatio {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+            calc: PNLCalc::SPOTPRICE,
+        })
+        .unwrap();
+    assert_eq!(margin_ratio, SignedDecimal::from(Uint128::new(100_000_000)));
+
+    // at the oracle price of 10 the position is worth 375, (60 - 225) / 375
+    let margin_ratio: SignedDecimal = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::MarginRatio {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+            calc: PNLCalc::ORACLE,
+        })
+        .unwrap();
+    assert_eq!(margin_ratio, SignedDecimal::new(Uint128::new(440_000_000), true));
+
+    // bob's short drops alice's margin ratio to 4.2%
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(1u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let margin_ratio: SignedDecimal = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::MarginRatio {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+            calc: PNLCalc::SPOTPRICE,
+        })
+        .unwrap();
+    assert_eq!(margin_ratio, SignedDecimal::from(Uint128::new(42_165_626)));
+
+    // the owner has no position
+    let margin_ratio: SignedDecimal = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::MarginRatio {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.owner.to_string(),
+            calc: PNLCalc::TWAP,
+        })
+        .unwrap();
+    assert_eq!(margin_ratio, SignedDecimal::zero());
+}
+
+#[test]
+fn test_add_and_remove_margin() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // bob has no position to add margin to
+    let add_msg = ExecuteMsg::AddMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(20u64),
+    };
+
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &add_msg,
+        &[]
+    );
+    assert_eq!(res.unwrap_err().to_string(), "Generic error: no position to add margin to");
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &add_msg,
+        &[]
+    ).unwrap();
+
+    // margin can also be added by sending it with the hook
+    let send_msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(10u64),
+        msg: to_binary(&Cw20HookMsg::AddMargin {
+            vamm: env.vamm.addr.to_string(),
+        }).unwrap(),
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &send_msg,
+        &[]
+    ).unwrap();
+
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(position.margin, to_decimals(90u64));
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_910u64));
+
+    // removing 31 would leave a margin ratio of 59 / 600, below the 10% initial
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::RemoveMargin {
+            vamm: env.vamm.addr.to_string(),
+            amount: to_decimals(31u64),
+        },
+        &[]
+    );
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "Generic error: position would be below the initial margin ratio",
+    );
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::RemoveMargin {
+            vamm: env.vamm.addr.to_string(),
+            amount: to_decimals(30u64),
+        },
+        &[]
+    ).unwrap();
+
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(position.margin, to_decimals(60u64));
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_940u64));
+
+    // more than the margin can't be removed
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::RemoveMargin {
+            vamm: env.vamm.addr.to_string(),
+            amount: to_decimals(61u64),
+        },
+        &[]
+    );
+    assert_eq!(res.unwrap_err().to_string(), "Generic error: insufficient margin");
+}
+
+#[test]
+fn test_pay_funding() {
+    let mut env = setup::setup();
+
+    // alice's long moves the spot price from 10 to 25.6 while the oracle stays at 10
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // funding can't be paid before the funding period has elapsed
+    let pay_funding_msg = ExecuteMsg::PayFunding {
+        vamm: env.vamm.addr.to_string(),
+    };
+
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &pay_funding_msg,
+        &[]
+    );
+    assert!(res.is_err());
+
+    // nor can the vamm's funding be settled other than by the engine
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(3_600);
+        block.height += 1;
+    });
+
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.vamm.addr.clone(),
+        &VammExecuteMsg::SettleFunding {},
+        &[]
+    );
+    assert!(res.is_err());
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &pay_funding_msg,
+        &[]
+    ).unwrap();
+
+    let vamm_state: StateResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.vamm.addr, &VammQueryMsg::State {})
+        .unwrap();
+    assert_eq!(vamm_state.cumulative_premium_fraction, SignedDecimal::from(Uint128::new(650_000_000)));
+
+    // there are no shorts to receive the funding so it goes to the insurance fund
+    let insurance_fund_balance = Cw20Contract(env.usdc.addr.clone())
+        .balance(&env.router, env.insurance_fund.addr.clone())
+        .unwrap();
+    assert_eq!(insurance_fund_balance, Uint128::new(24_375_000_000));
+
+    // the long pays 0.65 on its size of 37.5, 60 - 24.375 = 35.625
+    let balance: Uint128 = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(balance, Uint128::new(35_625_000_000));
+
+    // the funding is settled out of the margin when the position is next touched
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::AddMargin {
+            vamm: env.vamm.addr.to_string(),
+            amount: to_decimals(10u64),
+        },
+        &[]
+    ).unwrap();
+
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(position.margin, Uint128::new(45_625_000_000));
+    assert_eq!(position.premium_fraction, SignedDecimal::from(Uint128::new(650_000_000)));
+
+    // and it is only paid once
+    let balance: Uint128 = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::TraderBalance {
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    assert_eq!(balance, position.margin);
+}
+
+#[test]
+fn test_pay_funding_from_insurance_fund() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::BUY,
+            quote_asset_amount: to_decimals(60u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.usdc.addr.clone(),
+        &Cw20ExecuteMsg::Transfer {
+            recipient: env.insurance_fund.addr.to_string(),
+            amount: to_decimals(500u64),
+        },
+        &[]
+    ).unwrap();
+
+    // the oracle price of 30 is over the spot price of 25.6 so longs are paid
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+    let timestamp = env.router.block_info().time.seconds();
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.pricefeed.addr.clone(),
+        &PricefeedExecuteMsg::AppendPrice {
+            key: "USD".to_string(),
+            price: to_decimals(30u64),
+            timestamp,
+        },
+        &[]
+    ).unwrap();
+
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(3_600);
+        block.height += 1;
+    });
+
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::PayFunding {
+            vamm: env.vamm.addr.to_string(),
+        },
+        &[]
+    ).unwrap();
+
+    let funding_imbalance = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "funding_imbalance")
+        .unwrap();
+    // the long is paid 0.18... on its size of 37.5
+    assert_eq!(funding_imbalance.value, "-6874999987");
+
+    // there are no shorts to pay the long so the insurance fund pays it
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, Uint128::new(66_874_999_987));
+    let insurance_fund_balance = usdc.balance(&env.router, env.insurance_fund.addr.clone()).unwrap();
+    assert_eq!(insurance_fund_balance, Uint128::new(493_125_000_013));
+}
+
+#[test]
+fn test_bad_debt_pauses_the_vamm() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(100u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // the insurance fund is empty so can't cover alice's bad debt
+    let res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::Liquidate {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        },
+        &[]
+    ).unwrap();
+
+    let event = res.events
+        .iter()
+        .find(|e| e.ty == "wasm-bad_debt")
+        .unwrap();
+    let uncovered = event.attributes
+        .iter()
+        .find(|attr| attr.key == "uncovered")
+        .unwrap();
+    assert_eq!(uncovered.value, "432551020408");
+
+    let bad_debt: BadDebtResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::BadDebt {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap();
+    assert_eq!(
+        bad_debt,
+        BadDebtResponse {
+            total: Uint128::new(432_551_020_408),
+            socialized_loss: Uint128::zero(),
+            paused: true,
+        },
+    );
+
+    // no positions can be opened while the vamm is paused
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10u64),
+        leverage: to_decimals(1u64),
+        base_asset_amount_limit: None,
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    );
+    assert_eq!(res.unwrap_err().to_string(), "Generic error: vAMM is paused");
+
+    // only the owner can unpause it
+    let unpause_msg = ExecuteMsg::Unpause {
+        vamm: env.vamm.addr.to_string(),
+    };
+
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &unpause_msg,
+        &[]
+    );
+    assert!(res.is_err());
+
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &unpause_msg,
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+}
+
+#[test]
+fn test_bad_debt_is_socialized() {
+    let mut env = setup::setup();
+
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::UpdateConfig {
+            owner: None,
+            partial_liquidation_ratio: None,
+            spread_limit_ratio: None,
+            bad_debt_mode: Some(BadDebtMode::SOCIALIZE),
+        },
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(100u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::Liquidate {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        },
+        &[]
+    ).unwrap();
+
+    let bad_debt: BadDebtResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::BadDebt {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap();
+    assert_eq!(
+        bad_debt,
+        BadDebtResponse {
+            total: Uint128::new(432_551_020_408),
+            socialized_loss: Uint128::new(432_551_020_408),
+            paused: false,
+        },
+    );
+
+    // bob's profit of 489.79... pays the socialized loss
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::ClosePosition {
+            vamm: env.vamm.addr.to_string(),
+            quote_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    assert_eq!(realized_pnl.value, "57244897957");
+
+    let bad_debt: BadDebtResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::BadDebt {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap();
+    assert_eq!(bad_debt.socialized_loss, Uint128::zero());
+}
+
+#[test]
+fn test_bad_debt_is_socialized_pro_rata() {
+    let mut env = setup::setup();
+
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::UpdateConfig {
+            owner: None,
+            partial_liquidation_ratio: None,
+            spread_limit_ratio: None,
+            bad_debt_mode: Some(BadDebtMode::SOCIALIZE),
+        },
+        &[]
+    ).unwrap();
+
+    // fund the owner so they can take the other half of the short side
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.usdc.addr.clone(),
+        &Cw20ExecuteMsg::Transfer {
+            recipient: env.owner.to_string(),
+            amount: to_decimals(1000u64),
+        },
+        &[]
+    ).unwrap();
+
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.usdc.addr.clone(),
+        &Cw20ExecuteMsg::IncreaseAllowance {
+            spender: env.engine.addr.to_string(),
+            amount: to_decimals(1000u64),
+            expires: None,
+        },
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(50u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
 
-//     let send_msg = Cw20ExecuteMsg::Send {
-//         contract: env.engine.addr.to_string(),
-//         amount: to_decimals(20u64),
-//         msg: to_binary(&Cw20HookMsg::OpenPosition {
-//             vamm: env.vamm.addr.to_string(),
-//             side: Side::BUY,
-//             leverage: to_decimals(5u64),
-//         }).unwrap(),
-//     };
+    for trader in [env.bob.clone(), env.owner.clone()] {
+        let _res = env.router.execute_contract(
+            trader,
+            env.engine.addr.clone(),
+            &msg,
+            &[]
+        ).unwrap();
+    }
 
-//     let _res = env.router.execute_contract(
-//         env.alice.clone(),
-//         env.usdc.addr.clone(),
-//         &send_msg,
-//         &[]
-//     ).unwrap();
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::Liquidate {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        },
+        &[]
+    ).unwrap();
 
-//         // retrieve the vamm state
-//     let position: PositionResponse = env.router
-//         .wrap()
-//         .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
-//             vamm: env.vamm.addr.to_string(),
-//             trader: env.alice.to_string(),
-//         })
-//         .unwrap();
-//     assert_eq!(Uint128::new(11_111_111_112), position.size);
-//     assert_eq!(to_decimals(40), position.margin);
+    let bad_debt: BadDebtResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::BadDebt {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap();
+    assert_eq!(bad_debt.socialized_loss, Uint128::new(432_551_020_409));
 
-//     let send_msg = Cw20ExecuteMsg::Send {
-//         contract: env.engine.addr.to_string(),
-//         amount: to_decimals(20u64),
-//         msg: to_binary(&Cw20HookMsg::OpenPosition {
-//             vamm: env.vamm.addr.to_string(),
-//             side: Side::BUY,
-//             leverage: to_decimals(5u64),
-//         }).unwrap(),
-//     };
+    // the fund is topped up after the liquidation so the loss stays
+    // socialized but the profits can still be paid out
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.usdc.addr.clone(),
+        &Cw20ExecuteMsg::Transfer {
+            recipient: env.insurance_fund.addr.to_string(),
+            amount: to_decimals(1000u64),
+        },
+        &[]
+    ).unwrap();
 
-//     let _res = env.router.execute_contract(
-//         env.alice.clone(),
-//         env.usdc.addr.clone(),
-//         &send_msg,
-//         &[]
-//     ).unwrap();
+    // bob holds half of the open interest so only pays half of the loss
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::ClosePosition {
+            vamm: env.vamm.addr.to_string(),
+            quote_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
 
+    let socialized_loss = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "socialized_loss")
+        .unwrap();
+    assert_eq!(socialized_loss.value, "216275510204");
 
-//     // retrieve the vamm state
-//     let position: PositionResponse = env.router
-//         .wrap()
-//         .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
-//             vamm: env.vamm.addr.to_string(),
-//             trader: env.alice.to_string(),
-//         })
-//         .unwrap();
-//     assert_eq!(Uint128::new(1), position.size);
-//     assert_eq!(Uint128::new(1), position.margin);
+    let bad_debt: BadDebtResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::BadDebt {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap();
+    assert_eq!(bad_debt.socialized_loss, Uint128::new(216_275_510_205));
 
-// }
+    // the owner holds what is left of the open interest but only pays up to
+    // their profit, the rest stays socialized
+    let res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::ClosePosition {
+            vamm: env.vamm.addr.to_string(),
+            quote_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    assert_eq!(realized_pnl.value, "0");
+
+    let socialized_loss = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "socialized_loss")
+        .unwrap();
+    assert_eq!(socialized_loss.value, "68965517229");
+
+    let bad_debt: BadDebtResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::BadDebt {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap();
+    assert_eq!(bad_debt.socialized_loss, Uint128::new(147_309_992_976));
+}
+
+
+#[test]
+fn test_partial_liquidation_falls_back_to_full() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    // a partial liquidation ratio of 25% and a spread limit of 10%
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::UpdateConfig {
+            owner: None,
+            partial_liquidation_ratio: Some(Uint128::new(250_000_000)),
+            spread_limit_ratio: Some(Uint128::new(100_000_000)),
+            bad_debt_mode: None,
+        },
+        &[]
+    ).unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // bob's short takes the spot price over the spread limit below the oracle
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(500u64),
+        leverage: to_decimals(1u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // at the oracle price of 15 alice's margin ratio of 4% is above the fee
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(15);
+        block.height += 1;
+    });
+    let timestamp = env.router.block_info().time.seconds();
+    let _res = env.router.execute_contract(
+        env.owner.clone(),
+        env.pricefeed.addr.clone(),
+        &PricefeedExecuteMsg::AppendPrice {
+            key: "USD".to_string(),
+            price: to_decimals(15u64),
+            timestamp,
+        },
+        &[]
+    ).unwrap();
+
+    let owner_balance = usdc.balance(&env.router, env.owner.clone()).unwrap();
+
+    // closing a quarter at the spot price loses more than the margin, so the
+    // whole position is liquidated instead
+    let res = env.router.execute_contract(
+        env.owner.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::Liquidate {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        },
+        &[]
+    ).unwrap();
+
+    let attributes: Vec<_> = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .filter(|attr| attr.key == "partial")
+        .map(|attr| attr.value.as_str())
+        .collect();
+    assert_eq!(attributes, vec!["true", "false"]);
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    assert_eq!(realized_pnl.value, "-278761061950");
+
+    // the fee is on the notional both swaps closed the position for
+    let liquidator_fee = usdc.balance(&env.router, env.owner.clone()).unwrap() - owner_balance;
+    assert_eq!(liquidator_fee, Uint128::new(8_030_973_451));
+
+    let shortfall = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "shortfall")
+        .unwrap();
+    assert_eq!(shortfall.value, "226792035401");
+
+    let res = env.router
+        .wrap()
+        .query_wasm_smart::<PositionResponse>(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        });
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_reverse_position_in_profit() {
+    let mut env = setup::setup();
+
+    // set up cw20 helpers
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // bob's long puts alice's long in profit
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // the insurance fund covers the profit that is more than the engine holds
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.usdc.addr.clone(),
+        &Cw20ExecuteMsg::Transfer {
+            recipient: env.insurance_fund.addr.to_string(),
+            amount: to_decimals(500u64),
+        },
+        &[]
+    ).unwrap();
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+
+    // the short is worth more than alice's long so it closes the long and
+    // opens a short with what is left
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            quote_asset_amount: to_decimals(150u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    assert_eq!(realized_pnl.value, "394520547939");
+
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    // the long closed for 994.52... so 505.47... of the short is left
+    assert_eq!(position.size, SignedDecimal::new(Uint128::new(59_902_597_404), true));
+    assert_eq!(position.notional, Uint128::new(505_479_452_061));
+    assert_eq!(position.margin, Uint128::new(50_547_945_206));
+
+    // the margin is returned to alice with the profit, less the margin of the short
+    let alice_change = usdc.balance(&env.router, env.alice.clone()).unwrap() - alice_balance;
+    assert_eq!(alice_change, Uint128::new(403_972_602_733));
+}
+
+#[test]
+fn test_reduce_position_in_profit() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_amount_limit: None,
+    };
+
+    let _res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // bob's long puts alice's long in profit
+    let _res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &[]
+    ).unwrap();
+
+    // the short is worth less than alice's long so it only reduces it, even
+    // though it is more than the notional alice opened with
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side: Side::SELL,
+            quote_asset_amount: to_decimals(70u64),
+            leverage: to_decimals(10u64),
+            base_asset_amount_limit: None,
+        },
+        &[]
+    ).unwrap();
+
+    let realized_pnl = res.events
+        .iter()
+        .flat_map(|e| e.attributes.iter())
+        .find(|attr| attr.key == "realized_pnl")
+        .unwrap();
+    assert_eq!(realized_pnl.value, "360606060592");
+
+    let position: PositionResponse = env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+        })
+        .unwrap();
+    // 21.21... of the 37.5 long is closed so that share of the 600 notional
+    // is removed and its profit is added to the margin
+    assert_eq!(position.size, SignedDecimal::from(Uint128::new(16_287_878_787)));
+    assert_eq!(position.notional, Uint128::new(260_606_060_592));
+    assert_eq!(position.margin, Uint128::new(420_606_060_592));
+}
@@ -5,12 +5,19 @@ use cw20::{Cw20Coin, Cw20ExecuteMsg};
 use cw_multi_test::{App, AppBuilder, Contract, ContractWrapper, Executor};
 use cosmwasm_std::{Addr, Empty, Uint128};
 use margined_perp::margined_engine::{
-    InstantiateMsg,
+    BadDebtMode, InstantiateMsg,
+};
+use margined_perp::margined_insurance_fund::{
+    ExecuteMsg as InsuranceFundExecuteMsg, InstantiateMsg as InsuranceFundInstantiateMsg,
+};
+use margined_perp::margined_pricefeed::{
+    ExecuteMsg as PricefeedExecuteMsg, InstantiateMsg as PricefeedInstantiateMsg,
 };
 use margined_perp::margined_vamm::{
-    InstantiateMsg as VammInstantiateMsg,
+    ExecuteMsg as VammExecuteMsg, InstantiateMsg as VammInstantiateMsg,
 };
 
+#[allow(dead_code)]
 pub struct ContractInfo {
     pub addr: Addr,
     pub id: u64,
@@ -22,6 +29,8 @@ pub struct TestingEnv {
     pub alice: Addr,
     pub bob: Addr,
     pub usdc: ContractInfo,
+    pub pricefeed: ContractInfo,
+    pub insurance_fund: ContractInfo,
     pub vamm: ContractInfo,
     pub engine: ContractInfo,
 }
@@ -46,6 +55,24 @@ fn contract_vamm() -> Box<dyn Contract<Empty>> {
     Box::new(contract)
 }
 
+fn contract_pricefeed() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new_with_empty(
+        margined_pricefeed::contract::execute,
+        margined_pricefeed::contract::instantiate,
+        margined_pricefeed::contract::query,
+    );
+    Box::new(contract)
+}
+
+fn contract_insurance_fund() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new_with_empty(
+        margined_insurance_fund::contract::execute,
+        margined_insurance_fund::contract::instantiate,
+        margined_insurance_fund::contract::query,
+    );
+    Box::new(contract)
+}
+
 fn contract_engine() -> Box<dyn Contract<Empty>> {
     let contract = ContractWrapper::new_with_empty(
         execute,
@@ -69,6 +96,8 @@ pub fn setup() -> TestingEnv {
     let usdc_id = router.store_code(contract_cw20());
     let engine_id = router.store_code(contract_engine());
     let vamm_id = router.store_code(contract_vamm());
+    let pricefeed_id = router.store_code(contract_pricefeed());
+    let insurance_fund_id = router.store_code(contract_insurance_fund());
 
     let usdc_addr = router.instantiate_contract(
         usdc_id,
@@ -95,6 +124,30 @@ pub fn setup() -> TestingEnv {
         None
     ).unwrap();
 
+    let pricefeed_addr = router.instantiate_contract(
+        pricefeed_id,
+        owner.clone(),
+        &PricefeedInstantiateMsg {
+            updaters: vec![],
+        },
+        &[],
+        "pricefeed",
+        None
+    ).unwrap();
+
+    // the index price starts at the spot price of the vamm
+    let timestamp = router.block_info().time.seconds();
+    router.execute_contract(
+        owner.clone(),
+        pricefeed_addr.clone(),
+        &PricefeedExecuteMsg::AppendPrice {
+            key: "USD".to_string(),
+            price: to_decimals(10),
+            timestamp,
+        },
+        &[]
+    ).unwrap();
+
     let vamm_addr = router.instantiate_contract(
         vamm_id,
         owner.clone(),
@@ -104,13 +157,33 @@ pub fn setup() -> TestingEnv {
             base_asset: "USD".to_string(),
             quote_asset_reserve: to_decimals(1_000),
             base_asset_reserve: to_decimals(100),
-            funding_period: 3_600 as u64,
+            funding_period: 3_600_u64,
+            fluctuation_limit_ratio: Uint128::zero(),
+            max_holding_base_asset: Uint128::zero(),
+            open_interest_notional_cap: Uint128::zero(),
+            toll_ratio: Uint128::zero(),
+            spread_ratio: Uint128::zero(),
+            pricefeed: pricefeed_addr.to_string(),
+            margin_engine: None,
         },
         &[],
         "vamm",
         None
     ).unwrap();
 
+    // set up insurance fund contract
+    let insurance_fund_addr = router.instantiate_contract(
+        insurance_fund_id,
+        owner.clone(),
+        &InsuranceFundInstantiateMsg {
+            token: usdc_addr.to_string(),
+            margin_engine: None,
+        },
+        &[],
+        "insurance_fund",
+        None
+    ).unwrap();
+
     // set up margined engine contract    
     let engine_addr = router
         .instantiate_contract(
@@ -119,9 +192,14 @@ pub fn setup() -> TestingEnv {
             &InstantiateMsg {
                 decimals: 9u8,
                 eligible_collateral: usdc_addr.to_string(),
-                initial_margin_ratio: Uint128::from(100u128), 
-                maintenance_margin_ratio: Uint128::from(100u128), 
-                liquidation_fee: Uint128::from(100u128),
+                insurance_fund: insurance_fund_addr.to_string(),
+                fee_pool: "fee_pool".to_string(),
+                initial_margin_ratio: Uint128::from(100_000_000u128), 
+                maintenance_margin_ratio: Uint128::from(50_000_000u128), 
+                liquidation_fee: Uint128::from(25_000_000u128),
+                partial_liquidation_ratio: Uint128::zero(),
+                spread_limit_ratio: Uint128::zero(),
+                bad_debt_mode: BadDebtMode::PAUSE,
                 vamm: vec![vamm_addr.to_string()],
             },
             &[],
@@ -130,6 +208,34 @@ pub fn setup() -> TestingEnv {
         )
         .unwrap();
 
+    // register the margin engine with the vamm
+    router.execute_contract(
+        owner.clone(),
+        vamm_addr.clone(),
+        &VammExecuteMsg::UpdateConfig {
+            owner: None,
+            margin_engine: Some(engine_addr.to_string()),
+            pricefeed: None,
+            fluctuation_limit_ratio: None,
+            max_holding_base_asset: None,
+            open_interest_notional_cap: None,
+            toll_ratio: None,
+            spread_ratio: None,
+        },
+        &[]
+    ).unwrap();
+
+    // register the margin engine with the insurance fund
+    router.execute_contract(
+        owner.clone(),
+        insurance_fund_addr.clone(),
+        &InsuranceFundExecuteMsg::UpdateConfig {
+            owner: None,
+            margin_engine: Some(engine_addr.to_string()),
+        },
+        &[]
+    ).unwrap();
+
     // create allowance for alice
     router.execute_contract(
         alice.clone(),
@@ -142,6 +248,18 @@ pub fn setup() -> TestingEnv {
         &[]
     ).unwrap();
 
+    // create allowance for bob
+    router.execute_contract(
+        bob.clone(),
+        usdc_addr.clone(),
+        &Cw20ExecuteMsg::IncreaseAllowance {
+            spender: engine_addr.to_string(),
+            amount: to_decimals(2000),
+            expires: None,
+        },
+        &[]
+    ).unwrap();
+
     TestingEnv {
         router,
         owner,
@@ -151,6 +269,14 @@ pub fn setup() -> TestingEnv {
             addr: usdc_addr,
             id: usdc_id,
         },
+        pricefeed: ContractInfo {
+            addr: pricefeed_addr,
+            id: pricefeed_id,
+        },
+        insurance_fund: ContractInfo {
+            addr: insurance_fund_addr,
+            id: insurance_fund_id,
+        },
         vamm: ContractInfo {
             addr: vamm_addr,
             id: vamm_id,
@@ -164,5 +290,5 @@ pub fn setup() -> TestingEnv {
 
 // takes in a Uint128 and multiplies by the decimals just to make tests more legible
 pub fn to_decimals(input: u64) -> Uint128 {
-    return Uint128::from(input) * DECIMAL_MULTIPLIER;
+    Uint128::from(input) * DECIMAL_MULTIPLIER
 }
@@ -2,7 +2,7 @@ use crate::contract::{instantiate, execute, query};
 use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
 use cosmwasm_std::{Addr, from_binary, Uint128};
 use margined_perp::margined_engine::{
-    ConfigResponse, ExecuteMsg, InstantiateMsg, QueryMsg,
+    BadDebtMode, ConfigResponse, ExecuteMsg, InstantiateMsg, QueryMsg,
 };
 
 const TOKEN: &str = "token";
@@ -14,9 +14,14 @@ fn test_instantiation() {
     let msg = InstantiateMsg {
         decimals: 10u8,
         eligible_collateral: TOKEN.to_string(),
+        insurance_fund: "insurance_fund".to_string(),
+        fee_pool: "fee_pool".to_string(),
         initial_margin_ratio: Uint128::from(100u128), 
         maintenance_margin_ratio: Uint128::from(100u128), 
         liquidation_fee: Uint128::from(100u128),
+        partial_liquidation_ratio: Uint128::zero(),
+        spread_limit_ratio: Uint128::zero(),
+        bad_debt_mode: BadDebtMode::PAUSE,
         vamm: vec!["test".to_string()],
     };
     let info = mock_info(OWNER, &[]);
@@ -30,6 +35,11 @@ fn test_instantiation() {
         ConfigResponse {
             owner: info.sender.clone(),
             eligible_collateral: Addr::unchecked(TOKEN),
+            insurance_fund: Addr::unchecked("insurance_fund"),
+            fee_pool: Addr::unchecked("fee_pool"),
+            partial_liquidation_ratio: Uint128::zero(),
+            spread_limit_ratio: Uint128::zero(),
+            bad_debt_mode: BadDebtMode::PAUSE,
         }
     );
 }
@@ -40,9 +50,14 @@ fn test_update_config() {
     let msg = InstantiateMsg {
         decimals: 10u8,
         eligible_collateral: TOKEN.to_string(),
+        insurance_fund: "insurance_fund".to_string(),
+        fee_pool: "fee_pool".to_string(),
         initial_margin_ratio: Uint128::from(100u128), 
         maintenance_margin_ratio: Uint128::from(100u128), 
         liquidation_fee: Uint128::from(100u128),
+        partial_liquidation_ratio: Uint128::zero(),
+        spread_limit_ratio: Uint128::zero(),
+        bad_debt_mode: BadDebtMode::PAUSE,
         vamm: vec!["test".to_string()],
     };
     let info = mock_info(OWNER, &[]);
@@ -50,7 +65,10 @@ fn test_update_config() {
 
     // Update the config
     let msg = ExecuteMsg::UpdateConfig {
-        owner: "addr0001".to_string(),
+        owner: Some("addr0001".to_string()),
+        partial_liquidation_ratio: Some(Uint128::from(250_000_000u128)),
+        spread_limit_ratio: Some(Uint128::from(100_000_000u128)),
+        bad_debt_mode: Some(BadDebtMode::SOCIALIZE),
     };
 
     let info = mock_info(OWNER, &[]);
@@ -63,12 +81,20 @@ fn test_update_config() {
         ConfigResponse {
             owner: Addr::unchecked("addr0001".to_string()),
             eligible_collateral: Addr::unchecked(TOKEN),
+            insurance_fund: Addr::unchecked("insurance_fund"),
+            fee_pool: Addr::unchecked("fee_pool"),
+            partial_liquidation_ratio: Uint128::from(250_000_000u128),
+            spread_limit_ratio: Uint128::from(100_000_000u128),
+            bad_debt_mode: BadDebtMode::SOCIALIZE,
         }
     );
 
     // Update should fail
     let msg = ExecuteMsg::UpdateConfig {
-        owner: OWNER.to_string(),
+        owner: Some(OWNER.to_string()),
+        partial_liquidation_ratio: None,
+        spread_limit_ratio: None,
+        bad_debt_mode: None,
     };
 
     let info = mock_info(OWNER, &[]);
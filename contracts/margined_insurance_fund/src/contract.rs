@@ -0,0 +1,90 @@
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
+};
+use margined_perp::margined_insurance_fund::{ExecuteMsg, InstantiateMsg, QueryMsg};
+
+use crate::error::ContractError;
+use crate::{
+    handle::{payout, update_config, withdraw},
+    query::{query_balance, query_config, query_deficit_history},
+    state::{Config, store_config},
+};
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    let margin_engine = match msg.margin_engine {
+        Some(margin_engine) => Some(deps.api.addr_validate(&margin_engine)?),
+        None => None,
+    };
+
+    let config = Config {
+        owner: info.sender,
+        token: deps.api.addr_validate(&msg.token)?,
+        margin_engine,
+    };
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        ExecuteMsg::UpdateConfig {
+            owner,
+            margin_engine,
+        } => {
+            update_config(
+                deps,
+                info,
+                owner,
+                margin_engine,
+            )
+        },
+        ExecuteMsg::Withdraw {
+            amount,
+        } => {
+            withdraw(
+                deps,
+                env,
+                info,
+                amount,
+            )
+        },
+        ExecuteMsg::Payout {
+            amount,
+        } => {
+            payout(
+                deps,
+                env,
+                info,
+                amount,
+            )
+        },
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Balance {} => to_binary(&query_balance(deps, env)?),
+        QueryMsg::DeficitHistory {
+            start_after,
+            limit,
+        } => to_binary(&query_deficit_history(deps, start_after, limit)?),
+    }
+}
@@ -0,0 +1,142 @@
+use cosmwasm_std::{
+    to_binary, Addr, CosmosMsg, DepsMut, Env, MessageInfo, Response, StdResult,
+    Uint128, WasmMsg,
+};
+use cw20::Cw20ExecuteMsg;
+
+use crate::{
+    error::ContractError,
+    querier::query_token_balance,
+    state::{
+        Config, read_config, store_config,
+        Deficit, store_deficit,
+    },
+};
+
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: Option<String>,
+    margin_engine: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // change owner of insurance fund
+    if let Some(owner) = owner {
+        config.owner = deps.api.addr_validate(owner.as_str())?;
+    }
+
+    // change the margin engine that can withdraw
+    if let Some(margin_engine) = margin_engine {
+        config.margin_engine = Some(deps.api.addr_validate(margin_engine.as_str())?);
+    }
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default())
+}
+
+/// Pays the margin engine to cover bad debt, if the fund can't cover all of
+/// it the fund's balance is paid and the rest is left as the shortfall
+pub fn withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if !config.is_margin_engine(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if amount.is_zero() {
+        return Err(ContractError::InvalidAmount {});
+    }
+
+    let balance = query_token_balance(
+        &deps.as_ref(),
+        config.token.to_string(),
+        env.contract.address.to_string(),
+    )?;
+    let withdrawn = amount.min(balance);
+
+    store_deficit(deps.storage, &Deficit {
+        amount,
+        withdrawn,
+        timestamp: env.block.time,
+    })?;
+
+    let mut response = Response::new();
+    if !withdrawn.is_zero() {
+        response = response.add_message(execute_transfer(&config, &info.sender, withdrawn)?);
+    }
+
+    Ok(response
+        .add_attributes(vec![
+            ("action", "withdraw"),
+            ("amount", &amount.to_string()),
+            ("withdrawn", &withdrawn.to_string()),
+            ("shortfall", &(amount - withdrawn).to_string()),
+        ])
+    )
+}
+
+/// Pays the margin engine what it is short of paying out to traders, e.g. the
+/// profit of a trader that is more than the margin the engine holds. This
+/// isn't bad debt so it isn't recorded as a deficit.
+pub fn payout(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if !config.is_margin_engine(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if amount.is_zero() {
+        return Err(ContractError::InvalidAmount {});
+    }
+
+    let balance = query_token_balance(
+        &deps.as_ref(),
+        config.token.to_string(),
+        env.contract.address.to_string(),
+    )?;
+    if amount > balance {
+        return Err(ContractError::InsufficientBalance {});
+    }
+
+    Ok(Response::new()
+        .add_message(execute_transfer(&config, &info.sender, amount)?)
+        .add_attributes(vec![
+            ("action", "payout"),
+            ("amount", &amount.to_string()),
+        ])
+    )
+}
+
+fn execute_transfer(
+    config: &Config,
+    recipient: &Addr,
+    amount: Uint128,
+) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: config.token.to_string(),
+        funds: vec![],
+        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+            recipient: recipient.to_string(),
+            amount,
+        })?,
+    }))
+}
@@ -0,0 +1,9 @@
+pub mod contract;
+mod handle;
+mod querier;
+mod query;
+mod state;
+mod error;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,21 @@
+// Contains queries for external contracts
+use cosmwasm_std::{
+    to_binary, Deps, QueryRequest, StdResult, Uint128, WasmQuery,
+};
+use cw20::{BalanceResponse, Cw20QueryMsg};
+
+// returns the balance of the address in the cw20 token
+pub fn query_token_balance(
+    deps: &Deps,
+    token: String,
+    address: String,
+) -> StdResult<Uint128> {
+    let response: BalanceResponse = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: token,
+        msg: to_binary(&Cw20QueryMsg::Balance {
+            address,
+        })?,
+    }))?;
+
+    Ok(response.balance)
+}
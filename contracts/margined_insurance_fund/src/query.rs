@@ -0,0 +1,56 @@
+use cosmwasm_std::{Deps, Env, StdResult, Uint128};
+use margined_perp::margined_insurance_fund::{ConfigResponse, DeficitResponse};
+
+use crate::{
+    querier::query_token_balance,
+    state::{Config, read_config, read_deficit_history},
+};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+/// Queries contract Config
+pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config: Config = read_config(deps.storage)?;
+
+    Ok(
+        ConfigResponse {
+            owner: config.owner,
+            token: config.token,
+            margin_engine: config.margin_engine,
+        }
+    )
+}
+
+/// Queries the balance of the fund in the collateral token
+pub fn query_balance(deps: Deps, env: Env) -> StdResult<Uint128> {
+    let config: Config = read_config(deps.storage)?;
+
+    query_token_balance(
+        &deps,
+        config.token.to_string(),
+        env.contract.address.to_string(),
+    )
+}
+
+/// Queries the withdrawals to cover bad debt, oldest first, from the index
+/// after start_after
+pub fn query_deficit_history(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<DeficitResponse>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    Ok(
+        read_deficit_history(deps.storage, start_after, limit)?
+            .into_iter()
+            .map(|(index, deficit)| DeficitResponse {
+                index,
+                amount: deficit.amount,
+                withdrawn: deficit.withdrawn,
+                timestamp: deficit.timestamp,
+            })
+            .collect()
+    )
+}
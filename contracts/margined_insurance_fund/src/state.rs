@@ -0,0 +1,83 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Order, StdResult, Storage, Timestamp, Uint128};
+use cosmwasm_storage::{
+    Bucket, ReadonlyBucket,
+    bucket, bucket_read,
+    singleton, singleton_read,
+};
+
+pub static KEY_CONFIG: &[u8] = b"config";
+pub static KEY_DEFICIT_HISTORY: &[u8] = b"deficit_history";
+pub static KEY_DEFICIT_HISTORY_LENGTH: &[u8] = b"deficit_history_length";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub owner: Addr,
+    pub token: Addr, // the collateral the fund holds
+    pub margin_engine: Option<Addr>, // can withdraw to cover bad debt
+}
+
+impl Config {
+    /// returns true if the address is the margin engine
+    pub fn is_margin_engine(&self, addr: &Addr) -> bool {
+        self.margin_engine.as_ref() == Some(addr)
+    }
+}
+
+pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
+    singleton(storage, KEY_CONFIG).save(config)
+}
+
+pub fn read_config(storage: &dyn Storage) -> StdResult<Config> {
+    singleton_read(storage, KEY_CONFIG).load()
+}
+
+/// A withdrawal by the margin engine to cover bad debt, the fund may not have
+/// been able to pay all of it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Deficit {
+    pub amount: Uint128,
+    pub withdrawn: Uint128,
+    pub timestamp: Timestamp,
+}
+
+fn deficit_history_bucket(storage: &mut dyn Storage) -> Bucket<'_, Deficit> {
+    bucket(storage, KEY_DEFICIT_HISTORY)
+}
+
+fn deficit_history_bucket_read(storage: &dyn Storage) -> ReadonlyBucket<'_, Deficit> {
+    bucket_read(storage, KEY_DEFICIT_HISTORY)
+}
+
+/// Appends a deficit to the history, indexes start at zero
+pub fn store_deficit(storage: &mut dyn Storage, deficit: &Deficit) -> StdResult<()> {
+    let index = read_deficit_history_length(storage)?;
+    deficit_history_bucket(storage).save(&index.to_be_bytes(), deficit)?;
+    singleton(storage, KEY_DEFICIT_HISTORY_LENGTH).save(&(index + 1))
+}
+
+/// Returns the deficits in order from the index after start_after
+pub fn read_deficit_history(
+    storage: &dyn Storage,
+    start_after: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<(u64, Deficit)>> {
+    let start = start_after.map(|index| (index + 1).to_be_bytes().to_vec());
+
+    deficit_history_bucket_read(storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (key, deficit) = item?;
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&key);
+            Ok((u64::from_be_bytes(index), deficit))
+        })
+        .collect()
+}
+
+pub fn read_deficit_history_length(storage: &dyn Storage) -> StdResult<u64> {
+    Ok(singleton_read(storage, KEY_DEFICIT_HISTORY_LENGTH).may_load()?.unwrap_or_default())
+}
@@ -0,0 +1,216 @@
+use crate::contract::{instantiate, execute, query};
+use crate::testing::mock_querier::mock_dependencies;
+use cosmwasm_std::testing::{mock_env, mock_info};
+use cosmwasm_std::{Addr, from_binary, to_binary, CosmosMsg, Uint128, WasmMsg};
+use cw20::Cw20ExecuteMsg;
+use margined_perp::margined_insurance_fund::{
+    ConfigResponse, DeficitResponse, ExecuteMsg, InstantiateMsg, QueryMsg,
+};
+
+const OWNER: &str = "owner";
+const TOKEN: &str = "token";
+const ENGINE: &str = "engine";
+const DECIMAL_MULTIPLIER: Uint128 = Uint128::new(1_000_000_000);
+
+// takes in a Uint128 and multiplies by the decimals just to make tests more legible
+fn to_decimals(input: u64) -> Uint128 {
+    Uint128::from(input) * DECIMAL_MULTIPLIER
+}
+
+#[test]
+fn test_instantiation() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        token: TOKEN.to_string(),
+        margin_engine: Some(ENGINE.to_string()),
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        config,
+        ConfigResponse {
+            owner: Addr::unchecked(OWNER),
+            token: Addr::unchecked(TOKEN),
+            margin_engine: Some(Addr::unchecked(ENGINE)),
+        }
+    );
+}
+
+#[test]
+fn test_update_config() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        token: TOKEN.to_string(),
+        margin_engine: None,
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // Update the config
+    let msg = ExecuteMsg::UpdateConfig {
+        owner: Some("addr0001".to_string()),
+        margin_engine: Some(ENGINE.to_string()),
+    };
+
+    let info = mock_info(OWNER, &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        config,
+        ConfigResponse {
+            owner: Addr::unchecked("addr0001"),
+            token: Addr::unchecked(TOKEN),
+            margin_engine: Some(Addr::unchecked(ENGINE)),
+        }
+    );
+
+    // Update should fail as the owner has changed
+    let msg = ExecuteMsg::UpdateConfig {
+        owner: Some(OWNER.to_string()),
+        margin_engine: None,
+    };
+
+    let info = mock_info(OWNER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+}
+
+#[test]
+fn test_withdraw() {
+    let mut deps = mock_dependencies(&[]);
+    deps.querier.with_token_balance(to_decimals(100));
+
+    let msg = InstantiateMsg {
+        token: TOKEN.to_string(),
+        margin_engine: Some(ENGINE.to_string()),
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Balance {}).unwrap();
+    let balance: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(balance, to_decimals(100));
+
+    // only the margin engine can withdraw
+    let msg = ExecuteMsg::Withdraw {
+        amount: to_decimals(40),
+    };
+    let info = mock_info(OWNER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg.clone());
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    let info = mock_info(ENGINE, &[]);
+    let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: TOKEN.to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: ENGINE.to_string(),
+                amount: to_decimals(40),
+            }).unwrap(),
+        })
+    );
+
+    // the fund only has 60 left so the rest is a shortfall
+    deps.querier.with_token_balance(to_decimals(60));
+    let msg = ExecuteMsg::Withdraw {
+        amount: to_decimals(75),
+    };
+    let info = mock_info(ENGINE, &[]);
+    let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    assert_eq!(res.attributes[2].value, to_decimals(60).to_string());
+    assert_eq!(res.attributes[3].value, to_decimals(15).to_string());
+
+    // an exhausted fund pays nothing
+    deps.querier.with_token_balance(Uint128::zero());
+    let msg = ExecuteMsg::Withdraw {
+        amount: to_decimals(10),
+    };
+    let info = mock_info(ENGINE, &[]);
+    let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    assert!(res.messages.is_empty());
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::DeficitHistory {
+        start_after: None,
+        limit: None,
+    }).unwrap();
+    let deficits: Vec<DeficitResponse> = from_binary(&res).unwrap();
+    let timestamp = mock_env().block.time;
+    assert_eq!(
+        deficits,
+        vec![
+            DeficitResponse { index: 0, amount: to_decimals(40), withdrawn: to_decimals(40), timestamp },
+            DeficitResponse { index: 1, amount: to_decimals(75), withdrawn: to_decimals(60), timestamp },
+            DeficitResponse { index: 2, amount: to_decimals(10), withdrawn: Uint128::zero(), timestamp },
+        ]
+    );
+
+    // the history is paginated
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::DeficitHistory {
+        start_after: Some(0),
+        limit: Some(1),
+    }).unwrap();
+    let deficits: Vec<DeficitResponse> = from_binary(&res).unwrap();
+    assert_eq!(
+        deficits,
+        vec![DeficitResponse { index: 1, amount: to_decimals(75), withdrawn: to_decimals(60), timestamp }],
+    );
+}
+
+#[test]
+fn test_payout() {
+    let mut deps = mock_dependencies(&[]);
+    deps.querier.with_token_balance(to_decimals(100));
+
+    let msg = InstantiateMsg {
+        token: TOKEN.to_string(),
+        margin_engine: Some(ENGINE.to_string()),
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // only the margin engine can be paid out
+    let msg = ExecuteMsg::Payout {
+        amount: to_decimals(40),
+    };
+    let info = mock_info(OWNER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg.clone());
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    let info = mock_info(ENGINE, &[]);
+    let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: TOKEN.to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: ENGINE.to_string(),
+                amount: to_decimals(40),
+            }).unwrap(),
+        })
+    );
+
+    // a payout has to be paid in full
+    let msg = ExecuteMsg::Payout {
+        amount: to_decimals(150),
+    };
+    let info = mock_info(ENGINE, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Insufficient balance");
+
+    // and isn't a deficit
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::DeficitHistory {
+        start_after: None,
+        limit: None,
+    }).unwrap();
+    let deficits: Vec<DeficitResponse> = from_binary(&res).unwrap();
+    assert!(deficits.is_empty());
+}
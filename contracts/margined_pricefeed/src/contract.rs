@@ -0,0 +1,106 @@
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
+};
+use margined_perp::margined_pricefeed::{ExecuteMsg, InstantiateMsg, QueryMsg};
+
+use crate::error::ContractError;
+use crate::{
+    handle::{update_config, add_updater, remove_updater, append_price},
+    query::{
+        query_config, query_get_price, query_get_previous_price,
+        query_get_twap_price,
+    },
+    state::{Config, store_config},
+};
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    let config = Config {
+        owner: info.sender,
+        updaters: msg.updaters
+            .iter()
+            .map(|updater| deps.api.addr_validate(updater))
+            .collect::<StdResult<_>>()?,
+    };
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        ExecuteMsg::UpdateConfig {
+            owner,
+        } => {
+            update_config(
+                deps,
+                info,
+                owner,
+            )
+        },
+        ExecuteMsg::AddUpdater {
+            address,
+        } => {
+            add_updater(
+                deps,
+                info,
+                address,
+            )
+        },
+        ExecuteMsg::RemoveUpdater {
+            address,
+        } => {
+            remove_updater(
+                deps,
+                info,
+                address,
+            )
+        },
+        ExecuteMsg::AppendPrice {
+            key,
+            price,
+            timestamp,
+        } => {
+            append_price(
+                deps,
+                env,
+                info,
+                key,
+                price,
+                timestamp,
+            )
+        },
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Price {
+            key,
+        } => to_binary(&query_get_price(deps, key)?),
+        QueryMsg::PreviousPrice {
+            key,
+            round,
+        } => to_binary(&query_get_previous_price(deps, key, round)?),
+        QueryMsg::TwapPrice {
+            key,
+            interval,
+        } => to_binary(&query_get_twap_price(deps, env, key, interval)?),
+    }
+}
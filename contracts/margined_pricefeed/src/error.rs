@@ -0,0 +1,17 @@
+use cosmwasm_std::StdError;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("Unauthorized")]
+    Unauthorized {},
+
+    #[error("Price must be greater than zero")]
+    InvalidPrice {},
+
+    #[error("Timestamp must be after the latest price and not in the future")]
+    InvalidTimestamp {},
+}
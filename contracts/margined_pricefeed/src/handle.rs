@@ -0,0 +1,128 @@
+use cosmwasm_std::{DepsMut, Env, MessageInfo, Response, Uint128};
+
+use crate::{
+    error::ContractError,
+    state::{
+        Config, read_config, store_config,
+        read_latest_round, read_price_data, store_price_data,
+    },
+};
+
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // change owner of pricefeed
+    if let Some(owner) = owner {
+        config.owner = deps.api.addr_validate(owner.as_str())?;
+    }
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default())
+}
+
+/// Allows the address to append prices
+pub fn add_updater(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let address = deps.api.addr_validate(&address)?;
+    if !config.updaters.contains(&address) {
+        config.updaters.push(address.clone());
+    }
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "add_updater"),
+            ("address", address.as_str()),
+        ])
+    )
+}
+
+/// Stops the address from appending prices
+pub fn remove_updater(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let address = deps.api.addr_validate(&address)?;
+    config.updaters.retain(|a| *a != address);
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "remove_updater"),
+            ("address", address.as_str()),
+        ])
+    )
+}
+
+/// Appends a price for the key as the next round, the timestamp (in seconds)
+/// is when the price was observed so it has to be after the latest round
+pub fn append_price(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    key: String,
+    price: Uint128,
+    timestamp: u64,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if !config.is_updater(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if price.is_zero() {
+        return Err(ContractError::InvalidPrice {});
+    }
+
+    if timestamp > env.block.time.seconds() {
+        return Err(ContractError::InvalidTimestamp {});
+    }
+
+    let latest_round = read_latest_round(deps.storage, &key)?;
+    if latest_round != 0 && timestamp <= read_price_data(deps.storage, &key, latest_round)?.timestamp {
+        return Err(ContractError::InvalidTimestamp {});
+    }
+
+    let price_data = store_price_data(deps.storage, &key, price, timestamp)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "append_price"),
+            ("key", &key),
+            ("price", &price.to_string()),
+            ("timestamp", &timestamp.to_string()),
+            ("round_id", &price_data.round_id.to_string()),
+        ])
+    )
+}
@@ -0,0 +1,8 @@
+pub mod contract;
+mod handle;
+mod query;
+mod state;
+mod error;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,97 @@
+use cosmwasm_std::{Deps, Env, StdError, StdResult, Uint128};
+use margined_perp::margined_pricefeed::ConfigResponse;
+
+use crate::state::{
+    Config, read_config,
+    PriceData, read_latest_round, read_price_data,
+};
+
+/// Queries contract Config
+pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config: Config = read_config(deps.storage)?;
+
+    Ok(
+        ConfigResponse {
+            owner: config.owner,
+            updaters: config.updaters,
+        }
+    )
+}
+
+/// Queries the latest price of the key
+pub fn query_get_price(deps: Deps, key: String) -> StdResult<Uint128> {
+    let latest_round = read_latest_round(deps.storage, &key)?;
+    if latest_round == 0 {
+        return Err(StdError::generic_err(format!("no price for {}", key)));
+    }
+
+    Ok(read_price_data(deps.storage, &key, latest_round)?.price)
+}
+
+/// Queries the price of the key the number of rounds before the latest, i.e.
+/// a round of zero is the latest price
+pub fn query_get_previous_price(deps: Deps, key: String, round: u64) -> StdResult<Uint128> {
+    let latest_round = read_latest_round(deps.storage, &key)?;
+    if latest_round <= round {
+        return Err(StdError::generic_err(format!("not enough price history for {}", key)));
+    }
+
+    Ok(read_price_data(deps.storage, &key, latest_round - round)?.price)
+}
+
+/// Queries the time weighted average price of the key over the interval (in
+/// seconds). Each round's price is weighted by how long it was the latest.
+/// https://github.com/perpetual-protocol/perpetual-protocol/blob/release/v2.1.x/src/L2PriceFeed.sol
+pub fn query_get_twap_price(deps: Deps, env: Env, key: String, interval: u64) -> StdResult<Uint128> {
+    let mut round = read_latest_round(deps.storage, &key)?;
+    if round == 0 {
+        return Err(StdError::generic_err(format!("no price for {}", key)));
+    }
+
+    let latest: PriceData = read_price_data(deps.storage, &key, round)?;
+
+    let current_timestamp = env.block.time.seconds();
+    let base_timestamp = current_timestamp.saturating_sub(interval);
+
+    // the latest price is the price for the whole interval
+    if interval == 0 || latest.timestamp <= base_timestamp {
+        return Ok(latest.price);
+    }
+
+    let mut previous_timestamp = latest.timestamp;
+    let mut cumulative_time = current_timestamp - previous_timestamp;
+    let mut weighted_price = latest.price
+        .checked_mul(Uint128::from(cumulative_time))?;
+
+    loop {
+        // there is no more history so the average is over what there is
+        if round == 1 {
+            if cumulative_time == 0 {
+                return Ok(latest.price);
+            }
+
+            return Ok(weighted_price.checked_div(Uint128::from(cumulative_time))?);
+        }
+
+        round -= 1;
+        let price_data: PriceData = read_price_data(deps.storage, &key, round)?;
+
+        // the round started before the interval so only counts from its start
+        if price_data.timestamp <= base_timestamp {
+            weighted_price = weighted_price.checked_add(
+                price_data.price
+                    .checked_mul(Uint128::from(previous_timestamp - base_timestamp))?
+            )?;
+            break;
+        }
+
+        let time_fraction = previous_timestamp - price_data.timestamp;
+        weighted_price = weighted_price.checked_add(
+            price_data.price.checked_mul(Uint128::from(time_fraction))?
+        )?;
+        cumulative_time += time_fraction;
+        previous_timestamp = price_data.timestamp;
+    }
+
+    Ok(weighted_price.checked_div(Uint128::from(interval))?)
+}
@@ -0,0 +1,73 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, StdResult, Storage, Uint128};
+use cosmwasm_storage::{
+    Bucket, ReadonlyBucket,
+    bucket, bucket_read,
+    singleton, singleton_read,
+};
+
+pub static KEY_CONFIG: &[u8] = b"config";
+pub static KEY_PRICE_DATA: &[u8] = b"price_data";
+pub static KEY_PRICE_ROUNDS: &[u8] = b"price_rounds";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub owner: Addr,
+    pub updaters: Vec<Addr>, // can append prices as well as the owner
+}
+
+impl Config {
+    /// returns true if the address can append prices
+    pub fn is_updater(&self, addr: &Addr) -> bool {
+        self.owner == *addr || self.updaters.iter().any(|a| a == addr)
+    }
+}
+
+pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
+    singleton(storage, KEY_CONFIG).save(config)
+}
+
+pub fn read_config(storage: &dyn Storage) -> StdResult<Config> {
+    singleton_read(storage, KEY_CONFIG).load()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceData {
+    pub round_id: u64,
+    pub price: Uint128,
+    pub timestamp: u64,
+}
+
+fn price_data_bucket<'a>(storage: &'a mut dyn Storage, key: &str) -> Bucket<'a, PriceData> {
+    Bucket::multilevel(storage, &[KEY_PRICE_DATA, key.as_bytes()])
+}
+
+fn price_data_bucket_read<'a>(storage: &'a dyn Storage, key: &str) -> ReadonlyBucket<'a, PriceData> {
+    ReadonlyBucket::multilevel(storage, &[KEY_PRICE_DATA, key.as_bytes()])
+}
+
+/// Appends a price to the prices of the key, round ids start at one
+pub fn store_price_data(storage: &mut dyn Storage, key: &str, price: Uint128, timestamp: u64) -> StdResult<PriceData> {
+    let round_id = read_latest_round(storage, key)? + 1;
+    let price_data = PriceData {
+        round_id,
+        price,
+        timestamp,
+    };
+
+    price_data_bucket(storage, key).save(&round_id.to_be_bytes(), &price_data)?;
+    bucket(storage, KEY_PRICE_ROUNDS).save(key.as_bytes(), &round_id)?;
+
+    Ok(price_data)
+}
+
+pub fn read_price_data(storage: &dyn Storage, key: &str, round_id: u64) -> StdResult<PriceData> {
+    price_data_bucket_read(storage, key).load(&round_id.to_be_bytes())
+}
+
+/// Returns the id of the latest round of the key, zero if it has no prices
+pub fn read_latest_round(storage: &dyn Storage, key: &str) -> StdResult<u64> {
+    Ok(bucket_read(storage, KEY_PRICE_ROUNDS).may_load(key.as_bytes())?.unwrap_or_default())
+}
@@ -0,0 +1,289 @@
+use crate::contract::{instantiate, execute, query};
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+use cosmwasm_std::{Addr, from_binary, Uint128};
+use margined_perp::margined_pricefeed::{
+    ConfigResponse, ExecuteMsg, InstantiateMsg, QueryMsg,
+};
+
+const OWNER: &str = "owner";
+const UPDATER: &str = "updater";
+const KEY: &str = "ETH";
+const DECIMAL_MULTIPLIER: Uint128 = Uint128::new(1_000_000_000);
+
+// takes in a Uint128 and multiplies by the decimals just to make tests more legible
+fn to_decimals(input: u64) -> Uint128 {
+    Uint128::from(input) * DECIMAL_MULTIPLIER
+}
+
+#[test]
+fn test_instantiation() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        updaters: vec![UPDATER.to_string()],
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        config,
+        ConfigResponse {
+            owner: Addr::unchecked(OWNER),
+            updaters: vec![Addr::unchecked(UPDATER)],
+        }
+    );
+}
+
+#[test]
+fn test_update_config() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        updaters: vec![],
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // Update the config
+    let msg = ExecuteMsg::UpdateConfig {
+        owner: Some("addr0001".to_string()),
+    };
+
+    let info = mock_info(OWNER, &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.owner, Addr::unchecked("addr0001"));
+
+    // Update should fail as the owner has changed
+    let msg = ExecuteMsg::UpdateConfig {
+        owner: Some(OWNER.to_string()),
+    };
+
+    let info = mock_info(OWNER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+}
+
+#[test]
+fn test_add_and_remove_updater() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        updaters: vec![],
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let append_msg = ExecuteMsg::AppendPrice {
+        key: KEY.to_string(),
+        price: to_decimals(400),
+        timestamp: mock_env().block.time.seconds(),
+    };
+
+    // the updater isn't whitelisted yet
+    let info = mock_info(UPDATER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, append_msg.clone());
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    // only the owner can whitelist
+    let msg = ExecuteMsg::AddUpdater {
+        address: UPDATER.to_string(),
+    };
+    let info = mock_info(UPDATER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg.clone());
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    let info = mock_info(OWNER, &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let info = mock_info(UPDATER, &[]);
+    execute(deps.as_mut(), mock_env(), info, append_msg).unwrap();
+
+    let msg = ExecuteMsg::RemoveUpdater {
+        address: UPDATER.to_string(),
+    };
+    let info = mock_info(OWNER, &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.updaters, Vec::<Addr>::new());
+
+    let append_msg = ExecuteMsg::AppendPrice {
+        key: KEY.to_string(),
+        price: to_decimals(400),
+        timestamp: mock_env().block.time.seconds(),
+    };
+    let info = mock_info(UPDATER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, append_msg);
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+}
+
+#[test]
+fn test_append_price() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        updaters: vec![],
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let now = mock_env().block.time.seconds();
+
+    // there are no prices yet
+    let result = query(deps.as_ref(), mock_env(), QueryMsg::Price {
+        key: KEY.to_string(),
+    });
+    assert!(result.is_err());
+
+    let msg = ExecuteMsg::AppendPrice {
+        key: KEY.to_string(),
+        price: Uint128::zero(),
+        timestamp: now,
+    };
+    let info = mock_info(OWNER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Price must be greater than zero");
+
+    // prices can't be from the future
+    let msg = ExecuteMsg::AppendPrice {
+        key: KEY.to_string(),
+        price: to_decimals(400),
+        timestamp: now + 1,
+    };
+    let info = mock_info(OWNER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Timestamp must be after the latest price and not in the future",
+    );
+
+    let msg = ExecuteMsg::AppendPrice {
+        key: KEY.to_string(),
+        price: to_decimals(400),
+        timestamp: now - 15,
+    };
+    let info = mock_info(OWNER, &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // or older than the latest price
+    let msg = ExecuteMsg::AppendPrice {
+        key: KEY.to_string(),
+        price: to_decimals(405),
+        timestamp: now - 15,
+    };
+    let info = mock_info(OWNER, &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Timestamp must be after the latest price and not in the future",
+    );
+
+    let msg = ExecuteMsg::AppendPrice {
+        key: KEY.to_string(),
+        price: to_decimals(405),
+        timestamp: now,
+    };
+    let info = mock_info(OWNER, &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Price {
+        key: KEY.to_string(),
+    }).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(405));
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::PreviousPrice {
+        key: KEY.to_string(),
+        round: 1,
+    }).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(400));
+
+    // there are only two rounds
+    let result = query(deps.as_ref(), mock_env(), QueryMsg::PreviousPrice {
+        key: KEY.to_string(),
+        round: 2,
+    });
+    assert!(result.is_err());
+
+    // prices are kept per key
+    let result = query(deps.as_ref(), mock_env(), QueryMsg::Price {
+        key: "BTC".to_string(),
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_twap_price() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        updaters: vec![],
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // prices of 400, 405 and 410 each 15 seconds apart
+    let now = mock_env().block.time.seconds();
+    for (price, timestamp) in [(400, now - 45), (405, now - 30), (410, now - 15)] {
+        let msg = ExecuteMsg::AppendPrice {
+            key: KEY.to_string(),
+            price: to_decimals(price),
+            timestamp,
+        };
+        let info = mock_info(OWNER, &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    // (400 * 15 + 405 * 15 + 410 * 15) / 45 = 405
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::TwapPrice {
+        key: KEY.to_string(),
+        interval: 45,
+    }).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(405));
+
+    // (405 * 15 + 410 * 15) / 30 = 407.5
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::TwapPrice {
+        key: KEY.to_string(),
+        interval: 30,
+    }).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, Uint128::from(407_500_000_000u128));
+
+    // an interval longer than the history averages over the history
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::TwapPrice {
+        key: KEY.to_string(),
+        interval: 100,
+    }).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(405));
+
+    // no interval is the latest price
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::TwapPrice {
+        key: KEY.to_string(),
+        interval: 0,
+    }).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(410));
+
+    // the latest price counts until now, (405 * 15 + 410 * 30) / 45 = 408.33...
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(15);
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::TwapPrice {
+        key: KEY.to_string(),
+        interval: 45,
+    }).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, Uint128::from(408_333_333_333u128));
+
+    // and once it is older than the interval it is the only price
+    env.block.time = env.block.time.plus_seconds(45);
+    let res = query(deps.as_ref(), env, QueryMsg::TwapPrice {
+        key: KEY.to_string(),
+        interval: 45,
+    }).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(410));
+}
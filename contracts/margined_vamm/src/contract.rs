@@ -5,18 +5,31 @@ use cosmwasm_std::{
     StdResult, Uint128
 };
 use margined_perp::margined_vamm::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use margined_perp::signed_decimal::SignedDecimal;
 
 use crate::error::ContractError;
 use crate::{
-    handle::{update_config, swap_input, swap_output},
-    query::{query_config, query_state},
-    state::{Config, store_config, State, store_state}
+    handle::{
+        update_config, settle_funding, swap_input, swap_output, repeg, adjust_k,
+        set_open, shutdown, get_invariant_k,
+    },
+    query::{
+        query_config, query_state, query_twap, query_spot_price,
+        query_underlying_spot_price,
+        query_input_price, query_output_price, query_liquidity_history,
+        query_liquidity_history_length, query_calc_fee,
+    },
+    state::{
+        Config, store_config, State, store_state,
+        ReserveSnapshot, store_reserve_snapshot,
+        LiquidityChange, store_liquidity_change,
+    }
 };
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
@@ -24,6 +37,15 @@ pub fn instantiate(
         owner: info.sender.clone(),
         quote_asset: msg.quote_asset,
         base_asset: msg.base_asset,
+        pricefeed: deps.api.addr_validate(&msg.pricefeed)?,
+        margin_engine: msg.margin_engine
+            .map(|engine| deps.api.addr_validate(&engine))
+            .transpose()?,
+        fluctuation_limit_ratio: msg.fluctuation_limit_ratio,
+        max_holding_base_asset: msg.max_holding_base_asset,
+        open_interest_notional_cap: msg.open_interest_notional_cap,
+        toll_ratio: msg.toll_ratio,
+        spread_ratio: msg.spread_ratio,
     };
     
     store_config(deps.storage, &config)?;
@@ -33,13 +55,42 @@ pub fn instantiate(
     let state = State {
         base_asset_reserve: msg.base_asset_reserve,
         quote_asset_reserve: msg.quote_asset_reserve,
-        funding_rate: Uint128::zero(), // Initialise the funding rate as 0
+        funding_rate: SignedDecimal::zero(), // Initialise the funding rate as 0
         funding_period: msg.funding_period, // Funding period in seconds
-        decimals: decimals,
+        decimals,
+        next_funding_time: env.block.time.plus_seconds(msg.funding_period).seconds(),
+        cumulative_premium_fraction: SignedDecimal::zero(),
+        total_position_size: SignedDecimal::zero(),
+        cumulative_notional: SignedDecimal::zero(),
+        open: true,
+        shutdown: false,
+        settlement_price: Uint128::zero(),
+        block_height: env.block.height,
+        block_quote_asset_reserve: msg.quote_asset_reserve,
+        block_base_asset_reserve: msg.base_asset_reserve,
     };
 
     store_state(deps.storage, &state)?;
 
+    store_reserve_snapshot(
+        deps.storage,
+        &ReserveSnapshot {
+            quote_asset_reserve: state.quote_asset_reserve,
+            base_asset_reserve: state.base_asset_reserve,
+            timestamp: env.block.time,
+            block_height: env.block.height,
+        },
+    )?;
+
+    store_liquidity_change(
+        deps.storage,
+        &LiquidityChange {
+            invariant_k: get_invariant_k(&state)?,
+            cumulative_notional: state.cumulative_notional,
+            timestamp: env.block.time,
+        },
+    )?;
+
     Ok(Response::default())
 }
 
@@ -53,16 +104,31 @@ pub fn execute(
     match msg {
         ExecuteMsg::UpdateConfig {
             owner,
+            margin_engine,
+            pricefeed,
+            fluctuation_limit_ratio,
+            max_holding_base_asset,
+            open_interest_notional_cap,
+            toll_ratio,
+            spread_ratio,
         } => {
             update_config(
                 deps,
                 info,
                 owner,
+                margin_engine,
+                pricefeed,
+                fluctuation_limit_ratio,
+                max_holding_base_asset,
+                open_interest_notional_cap,
+                toll_ratio,
+                spread_ratio,
             )
         },
         ExecuteMsg::SwapInput {
             direction,
             quote_asset_amount,
+            base_asset_amount_limit,
         } => {
             swap_input(
                 deps,
@@ -70,11 +136,13 @@ pub fn execute(
                 info,
                 direction,
                 quote_asset_amount,
+                base_asset_amount_limit,
             )
         },
         ExecuteMsg::SwapOutput {
             direction,
             base_asset_amount,
+            quote_asset_amount_limit,
         } => { 
             swap_output(
                 deps,
@@ -82,15 +150,81 @@ pub fn execute(
                 info,
                 direction,
                 base_asset_amount,
+                quote_asset_amount_limit,
+            )
+        },
+        ExecuteMsg::SettleFunding {} => {
+            settle_funding(
+                deps,
+                env,
+                info,
+            )
+        },
+        ExecuteMsg::Repeg {
+            target_price,
+        } => {
+            repeg(
+                deps,
+                env,
+                info,
+                target_price,
+            )
+        },
+        ExecuteMsg::AdjustK {
+            multiplier,
+        } => {
+            adjust_k(
+                deps,
+                env,
+                info,
+                multiplier,
+            )
+        },
+        ExecuteMsg::SetOpen {
+            open,
+        } => {
+            set_open(
+                deps,
+                env,
+                info,
+                open,
+            )
+        },
+        ExecuteMsg::Shutdown {} => {
+            shutdown(
+                deps,
+                env,
+                info,
             )
         },
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Config {} => to_binary(&query_config(deps)?),
         QueryMsg::State {} => to_binary(&query_state(deps)?),
+        QueryMsg::Twap {
+            interval,
+        } => to_binary(&query_twap(deps, env, interval)?),
+        QueryMsg::SpotPrice {} => to_binary(&query_spot_price(deps)?),
+        QueryMsg::UnderlyingPrice {} => to_binary(&query_underlying_spot_price(deps)?),
+        QueryMsg::InputPrice {
+            direction,
+            quote_asset_amount,
+        } => to_binary(&query_input_price(deps, direction, quote_asset_amount)?),
+        QueryMsg::OutputPrice {
+            direction,
+            base_asset_amount,
+        } => to_binary(&query_output_price(deps, direction, base_asset_amount)?),
+        QueryMsg::LiquidityHistory {
+            start_after,
+            limit,
+        } => to_binary(&query_liquidity_history(deps, start_after, limit)?),
+        QueryMsg::LiquidityHistoryLength {} => to_binary(&query_liquidity_history_length(deps)?),
+        QueryMsg::CalcFee {
+            quote_asset_amount,
+        } => to_binary(&query_calc_fee(deps, quote_asset_amount)?),
     }
 }
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,6 +8,28 @@ pub enum ContractError {
 
     #[error("Unauthorized")]
     Unauthorized {},
+
+    #[error("Settle funding called too early")]
+    SettleFundingTooEarly {},
+
+    #[error("Swap returns {amount} which is less than the limit of {limit}")]
+    SwapBelowLimit { amount: Uint128, limit: Uint128 },
+
+    #[error("Swap costs {amount} which is more than the limit of {limit}")]
+    SwapAboveLimit { amount: Uint128, limit: Uint128 },
+
+    #[error("Price is over the fluctuation limit")]
+    OverFluctuationLimit {},
+
+    #[error("Price must be greater than zero")]
+    InvalidPrice {},
+
+    #[error("Multiplier must be greater than zero")]
+    InvalidMultiplier {},
+
+    #[error("Market is closed")]
+    MarketClosed {},
+
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
@@ -1,54 +1,393 @@
+use std::cmp::max;
+use std::convert::TryFrom;
+
 use cosmwasm_std::{
-    Addr, DepsMut, Env, MessageInfo, Response, StdResult, Storage, Uint128,
+    to_binary, Addr, DepsMut, Env, MessageInfo, Response, StdResult, Storage,
+    Uint128, Uint256,
 };
 
 use margined_perp::margined_vamm::Direction;
+use margined_perp::signed_decimal::SignedDecimal;
 use crate::{
     error::ContractError,
+    querier::query_underlying_twap_price,
     state::{
         Config, read_config, store_config,
         State, read_state, store_state,
+        ReserveSnapshot, read_reserve_snapshot, read_reserve_snapshot_counter,
+        store_reserve_snapshot, LiquidityChange, store_liquidity_change,
     },
 };
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_config(
     deps: DepsMut,
-    _info: MessageInfo,
-    owner: String,
+    info: MessageInfo,
+    owner: Option<String>,
+    margin_engine: Option<String>,
+    pricefeed: Option<String>,
+    fluctuation_limit_ratio: Option<Uint128>,
+    max_holding_base_asset: Option<Uint128>,
+    open_interest_notional_cap: Option<Uint128>,
+    toll_ratio: Option<Uint128>,
+    spread_ratio: Option<Uint128>,
 ) -> Result<Response, ContractError> {
-    let config = read_config(deps.storage)?;
+    let mut config: Config = read_config(deps.storage)?;
 
-    let new_config = Config {
-        owner: Addr::unchecked(owner),
-        quote_asset: config.quote_asset,
-        base_asset: config.base_asset,
-    };
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // change owner of vamm
+    if let Some(owner) = owner {
+        config.owner = deps.api.addr_validate(owner.as_str())?;
+    }
+
+    // change the margin engine allowed to swap
+    if let Some(margin_engine) = margin_engine {
+        config.margin_engine = Some(deps.api.addr_validate(margin_engine.as_str())?);
+    }
+
+    // change the pricefeed the index price is read from
+    if let Some(pricefeed) = pricefeed {
+        config.pricefeed = deps.api.addr_validate(pricefeed.as_str())?;
+    }
+
+    // change the fluctuation limit ratio, zero disables the limit
+    if let Some(fluctuation_limit_ratio) = fluctuation_limit_ratio {
+        config.fluctuation_limit_ratio = fluctuation_limit_ratio;
+    }
+
+    // change the open interest limits, zero removes the limit
+    if let Some(max_holding_base_asset) = max_holding_base_asset {
+        config.max_holding_base_asset = max_holding_base_asset;
+    }
+
+    if let Some(open_interest_notional_cap) = open_interest_notional_cap {
+        config.open_interest_notional_cap = open_interest_notional_cap;
+    }
+
+    // change the fees charged on trades
+    if let Some(toll_ratio) = toll_ratio {
+        config.toll_ratio = toll_ratio;
+    }
 
-    store_config(deps.storage, &new_config)?;
+    if let Some(spread_ratio) = spread_ratio {
+        config.spread_ratio = spread_ratio;
+    }
+
+    store_config(deps.storage, &config)?;
 
     Ok(Response::default())
 }
 
+pub const ONE_DAY_IN_SECONDS: u64 = 86_400;
+
+/// Settles funding for the last funding period, the premium between the
+/// mark price and the underlying index price sets the funding rate
+pub fn settle_funding(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+    let mut state: State = read_state(deps.storage)?;
+
+    // only the margin engine settles funding, as it tracks the premium fractions
+    require_margin_engine(&config, &info.sender)?;
+
+    if !state.open {
+        return Err(ContractError::MarketClosed {});
+    }
+
+    if env.block.time.seconds() < state.next_funding_time {
+        return Err(ContractError::SettleFundingTooEarly {});
+    }
+
+    // twap of the underlying asset over the funding period
+    let underlying_price = query_underlying_twap_price(
+        &deps.as_ref(),
+        config.pricefeed.to_string(),
+        config.base_asset,
+        state.funding_period,
+    )?;
+
+    // twap of the vAMM over the funding period
+    let mark_price = calc_twap(deps.storage, &env, state.funding_period)?;
+
+    let premium_fraction = update_funding_rate(
+        &mut state,
+        mark_price,
+        underlying_price,
+    )?;
+
+    // if funding is settled late, e.g. due to congestion, we don't want it
+    // to be settled again straight away so the next funding is at least half
+    // a funding period away
+    let min_next_funding_time = env.block.time
+        .plus_seconds(state.funding_period / 2)
+        .seconds();
+    state.next_funding_time = max(
+        state.next_funding_time + state.funding_period,
+        min_next_funding_time,
+    );
+
+    store_state(deps.storage, &state)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "settle_funding"),
+            ("premium_fraction", &premium_fraction.to_string()),
+            ("funding_rate", &state.funding_rate.to_string()),
+            ("underlying_price", &underlying_price.to_string()),
+        ])
+    )
+}
+
+/// Moves the spot price to the target price by rescaling the quote asset
+/// reserve, the base asset reserve is kept. The cost is what it costs the
+/// protocol for traders to close their net position at the new price rather
+/// than the old one, it is negative if the protocol gains. As this changes k
+/// the new k is appended to the liquidity history.
+pub fn repeg(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    target_price: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+    let state: State = read_state(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if target_price.is_zero() {
+        return Err(ContractError::InvalidPrice {});
+    }
+
+    let mut update_state = state.clone();
+    update_state.quote_asset_reserve = get_quote_asset_reserve_for_price(
+        state.base_asset_reserve,
+        target_price,
+        state.decimals,
+    )?;
+
+    // the repeg isn't a trade so the block starts again from the new reserves
+    update_state.block_height = env.block.height;
+    update_state.block_quote_asset_reserve = update_state.quote_asset_reserve;
+    update_state.block_base_asset_reserve = update_state.base_asset_reserve;
+
+    let cost = calc_repeg_cost(&state, &update_state)?;
+
+    store_state(deps.storage, &update_state)?;
+
+    store_reserve_snapshot(
+        deps.storage,
+        &ReserveSnapshot {
+            quote_asset_reserve: update_state.quote_asset_reserve,
+            base_asset_reserve: update_state.base_asset_reserve,
+            timestamp: env.block.time,
+            block_height: env.block.height,
+        },
+    )?;
+
+    let invariant_k = get_invariant_k(&update_state)?;
+    store_liquidity_change(
+        deps.storage,
+        &LiquidityChange {
+            invariant_k,
+            cumulative_notional: update_state.cumulative_notional,
+            timestamp: env.block.time,
+        },
+    )?;
+
+    Ok(Response::new()
+        .set_data(to_binary(&cost)?)
+        .add_attributes(vec![
+            ("action", "repeg"),
+            ("quote_asset_reserve", &update_state.quote_asset_reserve.to_string()),
+            ("repeg_cost", &cost.to_string()),
+            ("invariant_k", &invariant_k.to_string()),
+        ])
+    )
+}
+
+/// Scales the liquidity of the vAMM by multiplying both reserves by the
+/// multiplier, so the price stays the same. The new k is appended to the
+/// liquidity history.
+pub fn adjust_k(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    multiplier: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+    let mut state: State = read_state(deps.storage)?;
+
+    // check permission, the owner or margin engine can adjust k
+    if info.sender != config.owner {
+        require_margin_engine(&config, &info.sender)?;
+    }
+
+    if multiplier.is_zero() {
+        return Err(ContractError::InvalidMultiplier {});
+    }
+
+    state.quote_asset_reserve = multiply_by_ratio(
+        state.quote_asset_reserve,
+        multiplier,
+        state.decimals,
+    )?;
+    state.base_asset_reserve = multiply_by_ratio(
+        state.base_asset_reserve,
+        multiplier,
+        state.decimals,
+    )?;
+
+    // adjusting k isn't a trade so the block starts again from the new reserves
+    state.block_height = env.block.height;
+    state.block_quote_asset_reserve = state.quote_asset_reserve;
+    state.block_base_asset_reserve = state.base_asset_reserve;
+
+    store_state(deps.storage, &state)?;
+
+    store_reserve_snapshot(
+        deps.storage,
+        &ReserveSnapshot {
+            quote_asset_reserve: state.quote_asset_reserve,
+            base_asset_reserve: state.base_asset_reserve,
+            timestamp: env.block.time,
+            block_height: env.block.height,
+        },
+    )?;
+
+    let invariant_k = get_invariant_k(&state)?;
+    store_liquidity_change(
+        deps.storage,
+        &LiquidityChange {
+            invariant_k,
+            cumulative_notional: state.cumulative_notional,
+            timestamp: env.block.time,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "adjust_k"),
+            ("quote_asset_reserve", &state.quote_asset_reserve.to_string()),
+            ("base_asset_reserve", &state.base_asset_reserve.to_string()),
+            ("invariant_k", &invariant_k.to_string()),
+        ])
+    )
+}
+
+/// Opens or closes the market, swaps and funding are only allowed while the
+/// market is open
+pub fn set_open(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    open: bool,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+    let mut state: State = read_state(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // funding starts again from when the market is reopened, which also
+    // clears the settlement price of a previous shutdown
+    if open {
+        state.next_funding_time = env.block.time
+            .plus_seconds(state.funding_period)
+            .seconds();
+        state.shutdown = false;
+        state.settlement_price = Uint128::zero();
+    }
+    state.open = open;
+
+    store_state(deps.storage, &state)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "set_open"),
+            ("open", &open.to_string()),
+        ])
+    )
+}
+
+/// Closes the market for good and fixes the price that traders settle their
+/// positions at. It is the average price of the net position of traders, i.e.
+/// the quote asset it moved the reserves by over its size.
+/// https://github.com/perpetual-protocol/perpetual-protocol/blob/release/v2.1.x/src/Amm.sol
+pub fn shutdown(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+    let mut state: State = read_state(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    state.settlement_price = calc_settlement_price(&state)?;
+    state.open = false;
+    state.shutdown = true;
+
+    store_state(deps.storage, &state)?;
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "shutdown"),
+            ("settlement_price", &state.settlement_price.to_string()),
+        ])
+    )
+}
 
 // Function should only be called by the margin engine
 pub fn swap_input(
     deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
+    env: Env,
+    info: MessageInfo,
     direction: Direction,
     quote_asset_amount: Uint128,
+    base_asset_amount_limit: Option<Uint128>,
 ) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
     let state: State = read_state(deps.storage)?;
 
+    require_margin_engine(&config, &info.sender)?;
+    require_open(&state)?;
+
     let base_asset_amount = get_input_price_with_reserves(
         &state,
         &direction,
         quote_asset_amount
     )?;
 
+    // a long receives the base asset so it shouldn't get less than the
+    // limit, a short pays the base asset so it shouldn't pay more
+    if let Some(limit) = base_asset_amount_limit {
+        require_within_limit(
+            direction == Direction::AddToAmm,
+            base_asset_amount,
+            limit,
+        )?;
+    }
+
     update_reserve(
         deps.storage,
+        env,
+        config.fluctuation_limit_ratio,
         direction,
         quote_asset_amount,
         base_asset_amount
@@ -66,19 +405,34 @@ pub fn swap_input(
 // Function should only be called by the margin engine
 pub fn swap_output(
     deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
+    env: Env,
+    info: MessageInfo,
     direction: Direction,
     base_asset_amount: Uint128,
+    quote_asset_amount_limit: Option<Uint128>,
 ) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
     let state: State = read_state(deps.storage)?;
 
+    require_margin_engine(&config, &info.sender)?;
+    require_open(&state)?;
+
     let quote_asset_amount = get_output_price_with_reserves(
         &state,
         &direction,
         base_asset_amount
     )?;
 
+    // adding the base asset receives the quote asset so it shouldn't be
+    // less than the limit, removing it pays the quote so it shouldn't be more
+    if let Some(limit) = quote_asset_amount_limit {
+        require_within_limit(
+            direction == Direction::AddToAmm,
+            quote_asset_amount,
+            limit,
+        )?;
+    }
+
     // flip direction when updating reserve
     let mut update_direction = direction;
     if update_direction == Direction::AddToAmm {
@@ -89,6 +443,8 @@ pub fn swap_output(
 
     update_reserve(
         deps.storage,
+        env,
+        config.fluctuation_limit_ratio,
         update_direction,
         quote_asset_amount,
         base_asset_amount,
@@ -110,49 +466,50 @@ pub fn get_input_price_with_reserves(
     quote_asset_amount: Uint128,
 ) -> StdResult<Uint128> {
     if quote_asset_amount == Uint128::zero() {
-        Uint128::zero();
+        return Ok(Uint128::zero());
     }
 
-    // k = x * y (divided by decimal places)
-    let invariant_k = state.quote_asset_reserve
-        .checked_mul(state.base_asset_reserve)?
-        .checked_div(state.decimals)?;
+    // the reserves are multiplied together so the maths is done in 256 bits
+    let decimals = Uint256::from(state.decimals);
+    let quote_asset_reserve = Uint256::from(state.quote_asset_reserve);
+    let base_asset_reserve = Uint256::from(state.base_asset_reserve);
 
-    let quote_asset_after: Uint128;
-    let base_asset_after: Uint128;
+    // k = x * y (divided by decimal places)
+    let invariant_k = quote_asset_reserve
+        .checked_mul(base_asset_reserve)?
+        .checked_div(decimals)?;
 
-    match direction {
+    let quote_asset_after: Uint256 = match direction {
         Direction::AddToAmm => {
-            quote_asset_after = state.quote_asset_reserve
-                .checked_add(quote_asset_amount)?;
-
+            quote_asset_reserve
+                .checked_add(quote_asset_amount.into())?
         }
         Direction::RemoveFromAmm => {
-            quote_asset_after = state.quote_asset_reserve
-                .checked_sub(quote_asset_amount)?;
+            quote_asset_reserve
+                .checked_sub(quote_asset_amount.into())?
         }
-    }
-    
-    base_asset_after = invariant_k
-        .checked_mul(state.decimals)?
+    };
+
+    let base_asset_after: Uint256 = invariant_k
+        .checked_mul(decimals)?
         .checked_div(quote_asset_after)?;
 
-    let mut base_asset_bought = if base_asset_after > state.base_asset_reserve {
-        base_asset_after - state.base_asset_reserve
+    let mut base_asset_bought = if base_asset_after > base_asset_reserve {
+        base_asset_after - base_asset_reserve
     } else {
-        state.base_asset_reserve - base_asset_after
+        base_asset_reserve - base_asset_after
     };
 
-    let remainder = modulo(invariant_k, quote_asset_after);
-    if remainder != Uint128::zero() {
+    let remainder = modulo(invariant_k, quote_asset_after, decimals)?;
+    if remainder != Uint256::zero() {
         if *direction == Direction::AddToAmm {
-            base_asset_bought = base_asset_bought.checked_sub(Uint128::new(1u128))?;
+            base_asset_bought = base_asset_bought.checked_sub(Uint256::from(1u128))?;
         } else {
-            base_asset_bought = base_asset_bought.checked_add(Uint128::from(1u128))?;
+            base_asset_bought = base_asset_bought.checked_add(Uint256::from(1u128))?;
         }
     }
 
-    Ok(base_asset_bought)
+    Ok(Uint128::try_from(base_asset_bought)?)
 }
 
 pub fn get_output_price_with_reserves(
@@ -161,95 +518,446 @@ pub fn get_output_price_with_reserves(
     base_asset_amount: Uint128,
 ) -> StdResult<Uint128> {
     if base_asset_amount == Uint128::zero() {
-        Uint128::zero();
+        return Ok(Uint128::zero());
     }
-    println!("base asset input: {}", base_asset_amount);
-    let invariant_k = state.quote_asset_reserve 
-        .checked_mul(state.base_asset_reserve)?
-        .checked_div(state.decimals)?;
 
-    let quote_asset_after: Uint128;
-    let base_asset_after: Uint128;
+    // the reserves are multiplied together so the maths is done in 256 bits
+    let decimals = Uint256::from(state.decimals);
+    let quote_asset_reserve = Uint256::from(state.quote_asset_reserve);
+    let base_asset_reserve = Uint256::from(state.base_asset_reserve);
 
-    match direction {
+    let invariant_k = quote_asset_reserve
+        .checked_mul(base_asset_reserve)?
+        .checked_div(decimals)?;
+
+    let base_asset_after: Uint256 = match direction {
         Direction::AddToAmm => {
-            base_asset_after = state.base_asset_reserve
-                .checked_add(base_asset_amount)?;
+            base_asset_reserve
+                .checked_add(base_asset_amount.into())?
         }
         Direction::RemoveFromAmm => {
-            base_asset_after = state.base_asset_reserve
-                .checked_sub(base_asset_amount)?;
+            base_asset_reserve
+                .checked_sub(base_asset_amount.into())?
         }
-    }
-    println!("base asset input: {}", base_asset_amount);
-    quote_asset_after = invariant_k
-        .checked_mul(state.decimals)?
+    };
+
+    let quote_asset_after: Uint256 = invariant_k
+        .checked_mul(decimals)?
         .checked_div(base_asset_after)?;
-    
 
-    let mut quote_asset_sold = if quote_asset_after > state.quote_asset_reserve {
-        quote_asset_after - state.quote_asset_reserve
+    let mut quote_asset_sold = if quote_asset_after > quote_asset_reserve {
+        quote_asset_after - quote_asset_reserve
     } else {
-        state.quote_asset_reserve - quote_asset_after
+        quote_asset_reserve - quote_asset_after
     };
 
-    let remainder = modulo(invariant_k, base_asset_after);
-    if remainder != Uint128::zero() {
+    let remainder = modulo(invariant_k, base_asset_after, decimals)?;
+    if remainder != Uint256::zero() {
         if *direction == Direction::AddToAmm {
-            quote_asset_sold = quote_asset_sold.checked_sub(Uint128::from(1u128))?;
+            quote_asset_sold = quote_asset_sold.checked_sub(Uint256::from(1u128))?;
         } else {
-            quote_asset_sold = quote_asset_sold.checked_add(Uint128::new(1u128))?;
+            quote_asset_sold = quote_asset_sold.checked_add(Uint256::from(1u128))?;
+        }
+    }
+
+    Ok(Uint128::try_from(quote_asset_sold)?)
+}
+
+/// Updates the funding rate and cumulative premium fraction from the premium
+/// of the mark price over the underlying price, the premium is scaled by the
+/// fraction of a day that the funding period covers. A negative premium means
+/// that shorts pay longs.
+fn update_funding_rate(
+    state: &mut State,
+    mark_price: Uint128,
+    underlying_price: Uint128,
+) -> StdResult<SignedDecimal> {
+    let premium = SignedDecimal::from(mark_price)
+        .checked_sub(SignedDecimal::from(underlying_price))?;
+
+    let premium_fraction = premium
+        .checked_mul(Uint128::from(state.funding_period).into())?
+        .checked_div(Uint128::from(ONE_DAY_IN_SECONDS).into())?;
+
+    state.funding_rate = premium_fraction
+        .checked_mul(state.decimals.into())?
+        .checked_div(underlying_price.into())?;
+    state.cumulative_premium_fraction = state.cumulative_premium_fraction
+        .checked_add(premium_fraction)?;
+
+    Ok(premium_fraction)
+}
+
+// Calculates the cost of a repeg from the change in value of the traders'
+// net position, closing a net long returns quote asset so a higher value
+// costs the protocol, whereas closing a net short pays quote asset
+fn calc_repeg_cost(
+    state: &State,
+    update_state: &State,
+) -> StdResult<SignedDecimal> {
+    if state.total_position_size.is_zero() {
+        return Ok(SignedDecimal::zero());
+    }
+
+    let direction = if state.total_position_size.is_negative() {
+        Direction::RemoveFromAmm
+    } else {
+        Direction::AddToAmm
+    };
+    let size = state.total_position_size.abs();
+
+    let notional = SignedDecimal::from(
+        get_output_price_with_reserves(state, &direction, size)?
+    );
+    let update_notional = SignedDecimal::from(
+        get_output_price_with_reserves(update_state, &direction, size)?
+    );
+
+    if state.total_position_size.is_negative() {
+        notional.checked_sub(update_notional)
+    } else {
+        update_notional.checked_sub(notional)
+    }
+}
+
+// Returns the price that the net position of traders was opened at, it is
+// the spot price if traders have no net position
+fn calc_settlement_price(
+    state: &State,
+) -> StdResult<Uint128> {
+    if state.total_position_size.is_zero() {
+        return get_spot_price(state);
+    }
+
+    // the reserves with no position open are on the same curve, with the
+    // net position of traders returned to the base asset reserve
+    let base_asset_reserve = SignedDecimal::from(state.base_asset_reserve)
+        .checked_add(state.total_position_size)?
+        .abs();
+    let quote_asset_reserve = multiply_by_ratio(
+        get_invariant_k(state)?,
+        state.decimals,
+        base_asset_reserve,
+    )?;
+
+    let position_notional = if quote_asset_reserve > state.quote_asset_reserve {
+        quote_asset_reserve - state.quote_asset_reserve
+    } else {
+        state.quote_asset_reserve - quote_asset_reserve
+    };
+
+    multiply_by_ratio(
+        position_notional,
+        state.decimals,
+        state.total_position_size.abs(),
+    )
+}
+
+// Checks that the sender is the registered margin engine
+fn require_margin_engine(
+    config: &Config,
+    sender: &Addr,
+) -> Result<(), ContractError> {
+    match &config.margin_engine {
+        Some(margin_engine) if margin_engine == sender => Ok(()),
+        _ => Err(ContractError::Unauthorized {}),
+    }
+}
+
+// Checks that the market is open
+fn require_open(
+    state: &State,
+) -> Result<(), ContractError> {
+    if !state.open {
+        return Err(ContractError::MarketClosed {});
+    }
+
+    Ok(())
+}
+
+// Checks the amount of a swap against a slippage limit, an amount that is
+// received must be at least the limit and an amount paid at most the limit
+fn require_within_limit(
+    is_received: bool,
+    amount: Uint128,
+    limit: Uint128,
+) -> Result<(), ContractError> {
+    if is_received && amount < limit {
+        return Err(ContractError::SwapBelowLimit { amount, limit });
+    }
+
+    if !is_received && amount > limit {
+        return Err(ContractError::SwapAboveLimit { amount, limit });
+    }
+
+    Ok(())
+}
+
+// Checks whether the spot price has moved more than the fluctuation limit
+// ratio from the price at the start of the block, a ratio of zero disables it
+fn is_over_fluctuation_limit(
+    state: &State,
+    fluctuation_limit_ratio: Uint128,
+) -> StdResult<bool> {
+    if fluctuation_limit_ratio.is_zero() {
+        return Ok(false);
+    }
+
+    let block_price = get_price_with_reserves(
+        state.block_quote_asset_reserve,
+        state.block_base_asset_reserve,
+        state.decimals,
+    )?;
+    let price = get_spot_price(state)?;
+
+    let fluctuation = if price > block_price {
+        price - block_price
+    } else {
+        block_price - price
+    };
+    let limit = Uint256::from(block_price)
+        .checked_mul(fluctuation_limit_ratio.into())?
+        .checked_div(state.decimals.into())?;
+
+    Ok(Uint256::from(fluctuation) > limit)
+}
+
+/// Returns the spot price of the base asset in the quote asset, i.e.
+/// quote asset reserve / base asset reserve (in decimals)
+pub fn get_spot_price(
+    state: &State,
+) -> StdResult<Uint128> {
+    get_price_with_reserves(
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        state.decimals,
+    )
+}
+
+/// Returns the toll and spread fees charged on the quote asset amount of a
+/// trade, the ratios are in decimals
+pub fn calc_fee(
+    config: &Config,
+    state: &State,
+    quote_asset_amount: Uint128,
+) -> StdResult<(Uint128, Uint128)> {
+    let toll_fee = multiply_by_ratio(
+        quote_asset_amount,
+        config.toll_ratio,
+        state.decimals,
+    )?;
+    let spread_fee = multiply_by_ratio(
+        quote_asset_amount,
+        config.spread_ratio,
+        state.decimals,
+    )?;
+
+    Ok((toll_fee, spread_fee))
+}
+
+/// Returns k = x * y (divided by decimal places)
+pub fn get_invariant_k(
+    state: &State,
+) -> StdResult<Uint128> {
+    multiply_by_ratio(
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        state.decimals,
+    )
+}
+
+// Returns a * b / c without overflowing in the multiplication
+fn multiply_by_ratio(
+    a: Uint128,
+    b: Uint128,
+    c: Uint128,
+) -> StdResult<Uint128> {
+    let result = Uint256::from(a)
+        .checked_mul(b.into())?
+        .checked_div(c.into())?;
+
+    Ok(Uint128::try_from(result)?)
+}
+
+// Returns the quote asset reserve that gives the price with the base asset
+// reserve, i.e. the inverse of get_price_with_reserves
+fn get_quote_asset_reserve_for_price(
+    base_asset_reserve: Uint128,
+    price: Uint128,
+    decimals: Uint128,
+) -> StdResult<Uint128> {
+    multiply_by_ratio(price, base_asset_reserve, decimals)
+}
+
+fn get_price_with_reserves(
+    quote_asset_reserve: Uint128,
+    base_asset_reserve: Uint128,
+    decimals: Uint128,
+) -> StdResult<Uint128> {
+    multiply_by_ratio(quote_asset_reserve, decimals, base_asset_reserve)
+}
+
+/// Calculates the time weighted average price of the vAMM over the interval
+/// (in seconds) from the reserve snapshots. Each snapshot's price is weighted
+/// by how long it was the latest price.
+/// https://github.com/perpetual-protocol/perpetual-protocol/blob/release/v2.1.x/src/Amm.sol
+pub fn calc_twap(
+    storage: &dyn Storage,
+    env: &Env,
+    interval: u64,
+) -> StdResult<Uint128> {
+    let state: State = read_state(storage)?;
+
+    let mut counter = read_reserve_snapshot_counter(storage)?;
+    let mut current_snapshot = read_reserve_snapshot(storage, counter)?;
+    let mut current_price = get_price_with_reserves(
+        current_snapshot.quote_asset_reserve,
+        current_snapshot.base_asset_reserve,
+        state.decimals,
+    )?;
+
+    // return the latest price if there is no interval or history
+    if interval == 0 || counter == 1 {
+        return Ok(current_price);
+    }
+
+    let current_timestamp = env.block.time.seconds();
+    let base_timestamp = current_timestamp.saturating_sub(interval);
+
+    // the latest price is the price for the whole interval, otherwise it is
+    // weighted by the part of the interval since it was set
+    let mut previous_timestamp = current_snapshot.timestamp.seconds();
+    if previous_timestamp <= base_timestamp {
+        return Ok(current_price);
+    }
+
+    let period = current_timestamp - previous_timestamp;
+    let mut weighted_price = current_price.checked_mul(Uint128::from(period))?;
+
+    loop {
+        // if the snapshot history is shorter than the interval then we
+        // average over the history that we do have
+        if counter == 1 {
+            let history = current_timestamp - previous_timestamp;
+            if history == 0 {
+                return Ok(current_price);
+            }
+
+            return Ok(weighted_price.checked_div(Uint128::from(history))?);
+        }
+
+        counter -= 1;
+        current_snapshot = read_reserve_snapshot(storage, counter)?;
+        current_price = get_price_with_reserves(
+            current_snapshot.quote_asset_reserve,
+            current_snapshot.base_asset_reserve,
+            state.decimals,
+        )?;
+
+        let snapshot_timestamp = current_snapshot.timestamp.seconds();
+
+        // stop once we reach a snapshot from before the interval
+        if snapshot_timestamp <= base_timestamp {
+            let time_fraction = Uint128::from(previous_timestamp - base_timestamp);
+            weighted_price = weighted_price.checked_add(current_price.checked_mul(time_fraction)?)?;
+            break;
         }
+
+        let time_fraction = Uint128::from(previous_timestamp - snapshot_timestamp);
+        weighted_price = weighted_price.checked_add(current_price.checked_mul(time_fraction)?)?;
+        previous_timestamp = snapshot_timestamp;
     }
 
-    Ok(quote_asset_sold)
+    Ok(weighted_price.checked_div(Uint128::from(interval))?)
 }
 
 fn update_reserve(
     storage: &mut dyn Storage,
+    env: Env,
+    fluctuation_limit_ratio: Uint128,
     direction: Direction,
     quote_asset_amount: Uint128,
     base_asset_amount: Uint128,
-) -> StdResult<Response> {
+) -> Result<Response, ContractError> {
     let state: State = read_state(storage)?;
     let mut update_state = state.clone();
 
-    println!("Quote Asset Reserve: {}", update_state.quote_asset_reserve);
-    println!("Base Asset Reserve: {}", update_state.base_asset_reserve);
-    
+    // the first swap in a block records the reserves the block started
+    // with, so that every swap in the block is checked against them
+    if env.block.height != state.block_height {
+        update_state.block_height = env.block.height;
+        update_state.block_quote_asset_reserve = state.quote_asset_reserve;
+        update_state.block_base_asset_reserve = state.base_asset_reserve;
+    }
+
+    apply_swap_to_reserves(
+        &mut update_state,
+        direction,
+        quote_asset_amount,
+        base_asset_amount,
+    )?;
+
+    if is_over_fluctuation_limit(&update_state, fluctuation_limit_ratio)? {
+        return Err(ContractError::OverFluctuationLimit {});
+    }
+
+    store_state(storage, &update_state)?;
+
+    store_reserve_snapshot(
+        storage,
+        &ReserveSnapshot {
+            quote_asset_reserve: update_state.quote_asset_reserve,
+            base_asset_reserve: update_state.base_asset_reserve,
+            timestamp: env.block.time,
+            block_height: env.block.height,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_reserve")]))
+}
+
+fn apply_swap_to_reserves(
+    state: &mut State,
+    direction: Direction,
+    quote_asset_amount: Uint128,
+    base_asset_amount: Uint128,
+) -> StdResult<()> {
+    // traders hold the base asset that is removed from the vAMM
     match direction {
         Direction::AddToAmm => {
-            update_state.quote_asset_reserve = update_state.quote_asset_reserve
+            state.quote_asset_reserve = state.quote_asset_reserve
                 .checked_add(quote_asset_amount)?;
-            update_state.base_asset_reserve = state.base_asset_reserve
+            state.base_asset_reserve = state.base_asset_reserve
                 .checked_sub(base_asset_amount)?;
+            state.total_position_size = state.total_position_size
+                .checked_add(base_asset_amount.into())?;
+            state.cumulative_notional = state.cumulative_notional
+                .checked_add(quote_asset_amount.into())?;
         }
         Direction::RemoveFromAmm => {
-            update_state.base_asset_reserve = update_state.base_asset_reserve
+            state.base_asset_reserve = state.base_asset_reserve
                 .checked_add(base_asset_amount)?;
-            update_state.quote_asset_reserve = state.quote_asset_reserve
+            state.quote_asset_reserve = state.quote_asset_reserve
                 .checked_sub(quote_asset_amount)?;
+            state.total_position_size = state.total_position_size
+                .checked_sub(base_asset_amount.into())?;
+            state.cumulative_notional = state.cumulative_notional
+                .checked_sub(quote_asset_amount.into())?;
         }
     }
 
-    println!("Quote Asset Reserve: {}", update_state.quote_asset_reserve);
-    println!("Base Asset Reserve: {}", update_state.base_asset_reserve);
-
-    store_state(storage, &update_state)?;
-
-    Ok(Response::new().add_attributes(vec![("action", "update_reserve")]))
+    Ok(())
 }
 
-/// Does the modulus (%) operator on Uin128.
+/// Does the modulus (%) operator on Uint256.
 /// However it follows the design of the perpertual protocol decimals
 /// https://github.com/perpetual-protocol/perpetual-protocol/blob/release/v2.1.x/src/utils/Decimal.sol
 fn modulo(
-    a: Uint128,
-    b: Uint128,
-) -> Uint128 {
-    // TODO the decimals are currently hardcoded to 9dp, this needs to change in the future but without
-    // needing to pass the entire world to this function, i.e. access to storage
-    let a_decimals = a.checked_mul(Uint128::from(1_000_000_000u128)).unwrap();
-    let integral = a_decimals / b;
-    return a_decimals - (b*integral)
+    a: Uint256,
+    b: Uint256,
+    decimals: Uint256,
+) -> StdResult<Uint256> {
+    let a_decimals = a.checked_mul(decimals)?;
+
+    Ok(a_decimals.checked_rem(b)?)
 }
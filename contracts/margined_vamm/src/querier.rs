@@ -0,0 +1,37 @@
+// Contains queries for external contracts
+use cosmwasm_std::{
+    to_binary, Deps, QueryRequest, StdResult, Uint128, WasmQuery,
+};
+
+use margined_perp::margined_pricefeed::QueryMsg;
+
+// returns the twap of the underlying asset from the pricefeed
+// over the given interval (in seconds)
+pub fn query_underlying_twap_price(
+    deps: &Deps,
+    pricefeed: String,
+    key: String,
+    interval: u64,
+) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: pricefeed,
+        msg: to_binary(&QueryMsg::TwapPrice {
+            key,
+            interval,
+        })?,
+    }))
+}
+
+// returns the latest price of the underlying asset from the pricefeed
+pub fn query_underlying_price(
+    deps: &Deps,
+    pricefeed: String,
+    key: String,
+) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: pricefeed,
+        msg: to_binary(&QueryMsg::Price {
+            key,
+        })?,
+    }))
+}
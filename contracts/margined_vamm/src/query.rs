@@ -1,9 +1,23 @@
-use cosmwasm_std::{Deps, StdResult};
+use cosmwasm_std::{Deps, Env, StdResult, Uint128};
 use margined_perp::margined_vamm::{
-    ConfigResponse, StateResponse,
+    CalcFeeResponse, ConfigResponse, Direction, LiquidityChangeResponse,
+    StateResponse,
 };
 
-use crate::state::{Config, read_config, State, read_state};
+use crate::{
+    querier::query_underlying_price,
+    handle::{
+        calc_fee, calc_twap, get_input_price_with_reserves,
+        get_output_price_with_reserves, get_spot_price,
+    },
+    state::{
+        Config, read_config, State, read_state,
+        read_liquidity_history, read_liquidity_history_length,
+    },
+};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
 
 /// Queries contract Config
 pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
@@ -14,6 +28,13 @@ pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
             owner: config.owner,
             quote_asset: config.quote_asset,
             base_asset: config.base_asset,
+            pricefeed: config.pricefeed,
+            margin_engine: config.margin_engine,
+            fluctuation_limit_ratio: config.fluctuation_limit_ratio,
+            max_holding_base_asset: config.max_holding_base_asset,
+            open_interest_notional_cap: config.open_interest_notional_cap,
+            toll_ratio: config.toll_ratio,
+            spread_ratio: config.spread_ratio,
         }
     )
 }
@@ -28,7 +49,105 @@ pub fn query_state(deps: Deps) -> StdResult<StateResponse> {
             base_asset_reserve: state.base_asset_reserve,
             funding_rate: state.funding_rate,
             funding_period: state.funding_period,
-            decimals: state.decimals,            
+            decimals: state.decimals,
+            next_funding_time: state.next_funding_time,
+            cumulative_premium_fraction: state.cumulative_premium_fraction,
+            total_position_size: state.total_position_size,
+            open: state.open,
+            shutdown: state.shutdown,
+            settlement_price: state.settlement_price,
         }
     )
-}
\ No newline at end of file
+}
+
+/// Queries the twap of the vAMM over the interval (in seconds)
+pub fn query_twap(deps: Deps, env: Env, interval: u64) -> StdResult<Uint128> {
+    calc_twap(deps.storage, &env, interval)
+}
+
+/// Queries the current spot price of the vAMM
+pub fn query_spot_price(deps: Deps) -> StdResult<Uint128> {
+    let state: State = read_state(deps.storage)?;
+
+    get_spot_price(&state)
+}
+
+/// Queries the latest price of the underlying asset from the pricefeed, the
+/// index price that the spot price tracks
+pub fn query_underlying_spot_price(deps: Deps) -> StdResult<Uint128> {
+    let config: Config = read_config(deps.storage)?;
+
+    query_underlying_price(
+        &deps,
+        config.pricefeed.to_string(),
+        config.base_asset,
+    )
+}
+
+/// Queries the amount of base asset a swap of the quote asset amount
+/// would return, without executing the swap
+pub fn query_input_price(
+    deps: Deps,
+    direction: Direction,
+    quote_asset_amount: Uint128,
+) -> StdResult<Uint128> {
+    let state: State = read_state(deps.storage)?;
+
+    get_input_price_with_reserves(&state, &direction, quote_asset_amount)
+}
+
+/// Queries the amount of quote asset a swap of the base asset amount
+/// would return, without executing the swap
+pub fn query_output_price(
+    deps: Deps,
+    direction: Direction,
+    base_asset_amount: Uint128,
+) -> StdResult<Uint128> {
+    let state: State = read_state(deps.storage)?;
+
+    get_output_price_with_reserves(&state, &direction, base_asset_amount)
+}
+
+/// Queries the history of changes to the liquidity of the vAMM, in order
+/// from the index after start_after
+pub fn query_liquidity_history(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<LiquidityChangeResponse>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let history = read_liquidity_history(deps.storage, start_after, limit)?
+        .into_iter()
+        .map(|(index, change)| LiquidityChangeResponse {
+            index,
+            invariant_k: change.invariant_k,
+            cumulative_notional: change.cumulative_notional,
+            timestamp: change.timestamp,
+        })
+        .collect();
+
+    Ok(history)
+}
+
+/// Queries the number of entries in the liquidity history, the latest
+/// entry has the index one less than this
+pub fn query_liquidity_history_length(deps: Deps) -> StdResult<u64> {
+    read_liquidity_history_length(deps.storage)
+}
+
+/// Queries the toll and spread fees that are charged on the quote asset
+/// amount of a trade
+pub fn query_calc_fee(deps: Deps, quote_asset_amount: Uint128) -> StdResult<CalcFeeResponse> {
+    let config: Config = read_config(deps.storage)?;
+    let state: State = read_state(deps.storage)?;
+
+    let (toll_fee, spread_fee) = calc_fee(&config, &state, quote_asset_amount)?;
+
+    Ok(
+        CalcFeeResponse {
+            toll_fee,
+            spread_fee,
+        }
+    )
+}
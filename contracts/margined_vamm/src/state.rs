@@ -1,17 +1,33 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, StdResult, Storage, Uint128};
-use cosmwasm_storage::{singleton, singleton_read};
+use cosmwasm_std::{Addr, Order, StdResult, Storage, Timestamp, Uint128};
+use margined_perp::signed_decimal::SignedDecimal;
+use cosmwasm_storage::{
+    Bucket, ReadonlyBucket,
+    bucket, bucket_read,
+    singleton, singleton_read,
+};
 
 pub static KEY_CONFIG: &[u8] = b"config";
 pub static KEY_STATE: &[u8] = b"state";
+pub static KEY_RESERVE_SNAPSHOT: &[u8] = b"reserve_snapshot";
+pub static KEY_RESERVE_SNAPSHOT_COUNTER: &[u8] = b"reserve_snapshot_counter";
+pub static KEY_LIQUIDITY_HISTORY: &[u8] = b"liquidity_history";
+pub static KEY_LIQUIDITY_HISTORY_LENGTH: &[u8] = b"liquidity_history_length";
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub owner: Addr,
     pub quote_asset: String,
     pub base_asset: String,
+    pub pricefeed: Addr,
+    pub margin_engine: Option<Addr>,
+    pub fluctuation_limit_ratio: Uint128,
+    pub max_holding_base_asset: Uint128, // per trader, zero is no limit
+    pub open_interest_notional_cap: Uint128, // zero is no cap
+    pub toll_ratio: Uint128, // fee paid to the fee pool
+    pub spread_ratio: Uint128, // fee paid to the insurance fund
 }
 
 pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
@@ -27,9 +43,20 @@ pub fn read_config(storage: &dyn Storage) -> StdResult<Config> {
 pub struct State {
     pub quote_asset_reserve: Uint128,
     pub base_asset_reserve: Uint128,
-    pub funding_rate: Uint128,
+    pub funding_rate: SignedDecimal,
     pub decimals: Uint128,
     pub funding_period: u64,
+    pub next_funding_time: u64,
+    pub cumulative_premium_fraction: SignedDecimal,
+    pub total_position_size: SignedDecimal, // net base asset held by traders
+    pub cumulative_notional: SignedDecimal, // net quote asset paid by traders
+    pub open: bool,
+    pub shutdown: bool, // positions can be settled once the market is shut down
+    pub settlement_price: Uint128, // set when the market is shut down
+    // reserves at the start of the block, used to check price fluctuation
+    pub block_height: u64,
+    pub block_quote_asset_reserve: Uint128,
+    pub block_base_asset_reserve: Uint128,
 }
 
 pub fn store_state(storage: &mut dyn Storage, state: &State) -> StdResult<()> {
@@ -39,3 +66,89 @@ pub fn store_state(storage: &mut dyn Storage, state: &State) -> StdResult<()> {
 pub fn read_state(storage: &dyn Storage) -> StdResult<State> {
     singleton_read(storage, KEY_STATE).load()
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReserveSnapshot {
+    pub quote_asset_reserve: Uint128,
+    pub base_asset_reserve: Uint128,
+    pub timestamp: Timestamp,
+    pub block_height: u64,
+}
+
+fn reserve_snapshot_bucket(storage: &mut dyn Storage) -> Bucket<'_, ReserveSnapshot> {
+    bucket(storage, KEY_RESERVE_SNAPSHOT)
+}
+
+fn reserve_snapshot_bucket_read(storage: &dyn Storage) -> ReadonlyBucket<'_, ReserveSnapshot> {
+    bucket_read(storage, KEY_RESERVE_SNAPSHOT)
+}
+
+/// Stores a snapshot of the reserves, only one snapshot is kept per block
+/// so if the latest snapshot is from the current block it is overwritten
+pub fn store_reserve_snapshot(storage: &mut dyn Storage, snapshot: &ReserveSnapshot) -> StdResult<()> {
+    let mut counter = read_reserve_snapshot_counter(storage)?;
+
+    if counter == 0 || read_reserve_snapshot(storage, counter)?.block_height != snapshot.block_height {
+        counter += 1;
+        singleton(storage, KEY_RESERVE_SNAPSHOT_COUNTER).save(&counter)?;
+    }
+
+    reserve_snapshot_bucket(storage).save(&counter.to_be_bytes(), snapshot)
+}
+
+pub fn read_reserve_snapshot(storage: &dyn Storage, index: u64) -> StdResult<ReserveSnapshot> {
+    reserve_snapshot_bucket_read(storage).load(&index.to_be_bytes())
+}
+
+/// Returns the index of the latest snapshot, indexes start at one
+pub fn read_reserve_snapshot_counter(storage: &dyn Storage) -> StdResult<u64> {
+    Ok(singleton_read(storage, KEY_RESERVE_SNAPSHOT_COUNTER).may_load()?.unwrap_or_default())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidityChange {
+    pub invariant_k: Uint128,
+    pub cumulative_notional: SignedDecimal,
+    pub timestamp: Timestamp,
+}
+
+fn liquidity_history_bucket(storage: &mut dyn Storage) -> Bucket<'_, LiquidityChange> {
+    bucket(storage, KEY_LIQUIDITY_HISTORY)
+}
+
+fn liquidity_history_bucket_read(storage: &dyn Storage) -> ReadonlyBucket<'_, LiquidityChange> {
+    bucket_read(storage, KEY_LIQUIDITY_HISTORY)
+}
+
+/// Appends a change of liquidity to the history, indexes start at zero
+/// which is the liquidity the vAMM was instantiated with
+pub fn store_liquidity_change(storage: &mut dyn Storage, change: &LiquidityChange) -> StdResult<()> {
+    let length = read_liquidity_history_length(storage)?;
+
+    liquidity_history_bucket(storage).save(&length.to_be_bytes(), change)?;
+    singleton(storage, KEY_LIQUIDITY_HISTORY_LENGTH).save(&(length + 1))
+}
+
+pub fn read_liquidity_history_length(storage: &dyn Storage) -> StdResult<u64> {
+    Ok(singleton_read(storage, KEY_LIQUIDITY_HISTORY_LENGTH).may_load()?.unwrap_or_default())
+}
+
+/// Returns the changes of liquidity in order from the index after start_after
+pub fn read_liquidity_history(
+    storage: &dyn Storage,
+    start_after: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<(u64, LiquidityChange)>> {
+    let start = start_after.map(|index| (index + 1).to_be_bytes().to_vec());
+
+    liquidity_history_bucket_read(storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (key, change) = item?;
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&key);
+            Ok((u64::from_be_bytes(index), change))
+        })
+        .collect()
+}
@@ -1,3 +1,4 @@
+mod mock_querier;
 mod setup;
 mod tests;
 mod swap_tests;
\ No newline at end of file
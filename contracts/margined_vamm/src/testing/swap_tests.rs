@@ -1,5 +1,6 @@
 use cosmwasm_std::Uint128;
 use margined_perp::margined_vamm::Direction;
+use margined_perp::signed_decimal::SignedDecimal;
 use crate::{
     handle::{
         get_input_price_with_reserves,
@@ -20,9 +21,19 @@ fn test_get_input_and_output_price() {
     let state = State {
         quote_asset_reserve: to_decimals(1_000),
         base_asset_reserve: to_decimals(100),
-        funding_rate: Uint128::from(1_000u128),
-        funding_period: 3_600 as u64,
+        funding_rate: SignedDecimal::from(Uint128::from(1_000u128)),
+        funding_period: 3_600_u64,
         decimals: DECIMAL_MULTIPLIER,
+        next_funding_time: 0u64,
+        cumulative_premium_fraction: SignedDecimal::zero(),
+        total_position_size: SignedDecimal::zero(),
+        cumulative_notional: SignedDecimal::zero(),
+        open: true,
+        shutdown: false,
+        settlement_price: Uint128::zero(),
+        block_height: 0u64,
+        block_quote_asset_reserve: to_decimals(1_000),
+        block_base_asset_reserve: to_decimals(100),
     };
 
     // amount = 100(quote asset reserved) - (100 * 1000) / (1000 + 50) = 4.7619...
@@ -83,3 +94,192 @@ fn test_get_input_and_output_price() {
     ).unwrap();
     assert_eq!(result, to_decimals(600));
 }
+
+#[test]
+fn test_get_input_and_output_price_6_decimals() {
+    let decimals = Uint128::from(1_000_000u128);
+    let state = State {
+        quote_asset_reserve: Uint128::from(1_000u128) * decimals,
+        base_asset_reserve: Uint128::from(100u128) * decimals,
+        funding_rate: SignedDecimal::zero(),
+        funding_period: 3_600_u64,
+        decimals,
+        next_funding_time: 0u64,
+        cumulative_premium_fraction: SignedDecimal::zero(),
+        total_position_size: SignedDecimal::zero(),
+        cumulative_notional: SignedDecimal::zero(),
+        open: true,
+        shutdown: false,
+        settlement_price: Uint128::zero(),
+        block_height: 0u64,
+        block_quote_asset_reserve: Uint128::from(1_000u128) * decimals,
+        block_base_asset_reserve: Uint128::from(100u128) * decimals,
+    };
+
+    // amount = 100 - (100 * 1000) / (1000 + 50) = 4.761904
+    let result = get_input_price_with_reserves(
+        &state,
+        &Direction::AddToAmm,
+        Uint128::from(50u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(4_761_904u128));
+
+    // amount = (100 * 1000) / (1000 - 50) - 100 = 5.263158
+    let result = get_input_price_with_reserves(
+        &state,
+        &Direction::RemoveFromAmm,
+        Uint128::from(50u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(5_263_158u128));
+
+    // amount = 1000 - (100 * 1000) / (100 + 5) = 47.619047
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::AddToAmm,
+        Uint128::from(5u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(47_619_047u128));
+
+    // a dividable number should not plus 1 at mantissa
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::AddToAmm,
+        Uint128::from(25u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(200u128) * decimals);
+
+    // amount = (100 * 1000) / (100 - 5) - 1000 = 52.631579
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::RemoveFromAmm,
+        Uint128::from(5u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(52_631_579u128));
+
+    // leaves 0.262144 base which divides the invariant at 9 decimals but
+    // not at 6, so the amount still needs rounding up
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::RemoveFromAmm,
+        Uint128::from(100u128) * decimals - Uint128::from(262_144u128),
+    ).unwrap();
+    assert_eq!(result, Uint128::from(380_469_726_563u128));
+}
+
+#[test]
+fn test_get_input_and_output_price_12_decimals() {
+    let decimals = Uint128::from(1_000_000_000_000u128);
+    let state = State {
+        quote_asset_reserve: Uint128::from(1_000u128) * decimals,
+        base_asset_reserve: Uint128::from(100u128) * decimals,
+        funding_rate: SignedDecimal::zero(),
+        funding_period: 3_600_u64,
+        decimals,
+        next_funding_time: 0u64,
+        cumulative_premium_fraction: SignedDecimal::zero(),
+        total_position_size: SignedDecimal::zero(),
+        cumulative_notional: SignedDecimal::zero(),
+        open: true,
+        shutdown: false,
+        settlement_price: Uint128::zero(),
+        block_height: 0u64,
+        block_quote_asset_reserve: Uint128::from(1_000u128) * decimals,
+        block_base_asset_reserve: Uint128::from(100u128) * decimals,
+    };
+
+    // amount = 100 - (100 * 1000) / (1000 + 50) = 4.761904761904
+    let result = get_input_price_with_reserves(
+        &state,
+        &Direction::AddToAmm,
+        Uint128::from(50u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(4_761_904_761_904u128));
+
+    // amount = (100 * 1000) / (1000 - 50) - 100 = 5.263157894737
+    let result = get_input_price_with_reserves(
+        &state,
+        &Direction::RemoveFromAmm,
+        Uint128::from(50u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(5_263_157_894_737u128));
+
+    // amount = 1000 - (100 * 1000) / (100 + 5) = 47.619047619047
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::AddToAmm,
+        Uint128::from(5u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(47_619_047_619_047u128));
+
+    // a dividable number should not plus 1 at mantissa
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::AddToAmm,
+        Uint128::from(25u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(200u128) * decimals);
+
+    // amount = (100 * 1000) / (100 - 5) - 1000 = 52.631578947369
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::RemoveFromAmm,
+        Uint128::from(5u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(52_631_578_947_369u128));
+}
+
+#[test]
+fn test_get_input_and_output_price_large_reserves() {
+    // 10,000,000 quote and 5,000 base at 18 decimals, the product of the
+    // reserves doesn't fit in 128 bits
+    let decimals = Uint128::from(1_000_000_000_000_000_000u128);
+    let state = State {
+        quote_asset_reserve: Uint128::from(10_000_000u128) * decimals,
+        base_asset_reserve: Uint128::from(5_000u128) * decimals,
+        funding_rate: SignedDecimal::zero(),
+        funding_period: 3_600_u64,
+        decimals,
+        next_funding_time: 0u64,
+        cumulative_premium_fraction: SignedDecimal::zero(),
+        total_position_size: SignedDecimal::zero(),
+        cumulative_notional: SignedDecimal::zero(),
+        open: true,
+        shutdown: false,
+        settlement_price: Uint128::zero(),
+        block_height: 0u64,
+        block_quote_asset_reserve: Uint128::from(10_000_000u128) * decimals,
+        block_base_asset_reserve: Uint128::from(5_000u128) * decimals,
+    };
+
+    // amount = 5000 - (5000 * 10000000) / (10000000 + 50000) = 24.875621890547263681
+    let result = get_input_price_with_reserves(
+        &state,
+        &Direction::AddToAmm,
+        Uint128::from(50_000u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(24_875_621_890_547_263_681u128));
+
+    // amount = (5000 * 10000000) / (10000000 - 50000) - 5000 = 25.125628140703517588
+    let result = get_input_price_with_reserves(
+        &state,
+        &Direction::RemoveFromAmm,
+        Uint128::from(50_000u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(25_125_628_140_703_517_588u128));
+
+    // amount = 10000000 - (5000 * 10000000) / (5000 + 25) = 49751.243781094527363184
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::AddToAmm,
+        Uint128::from(25u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(49_751_243_781_094_527_363_184u128));
+
+    // amount = (5000 * 10000000) / (5000 - 25) - 10000000 = 50251.256281407035175880
+    let result = get_output_price_with_reserves(
+        &state,
+        &Direction::RemoveFromAmm,
+        Uint128::from(25u128) * decimals,
+    ).unwrap();
+    assert_eq!(result, Uint128::from(50_251_256_281_407_035_175_880u128));
+}
@@ -2,13 +2,17 @@ use crate::contract::{instantiate, execute, query};
 use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
 use cosmwasm_std::{Addr, from_binary, Uint128};
 use margined_perp::margined_vamm::{
+    CalcFeeResponse,
     ConfigResponse,
     ExecuteMsg,
     InstantiateMsg,
     QueryMsg,
     StateResponse,
     Direction,
+    LiquidityChangeResponse,
 };
+use margined_perp::signed_decimal::SignedDecimal;
+use crate::testing::mock_querier;
 use crate::testing::setup::{
     DECIMAL_MULTIPLIER, to_decimals,
 };
@@ -22,7 +26,14 @@ fn test_instantiation() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: Uint128::from(100u128),
         base_asset_reserve: Uint128::from(10_000u128),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -36,6 +47,13 @@ fn test_instantiation() {
             owner: info.sender.clone(),
             quote_asset: "ETH".to_string(),
             base_asset: "USD".to_string(),
+            pricefeed: Addr::unchecked("oracle".to_string()),
+            margin_engine: Some(Addr::unchecked("addr0000".to_string())),
+            fluctuation_limit_ratio: Uint128::zero(),
+            max_holding_base_asset: Uint128::zero(),
+            open_interest_notional_cap: Uint128::zero(),
+            toll_ratio: Uint128::zero(),
+            spread_ratio: Uint128::zero(),
         }
     );
 
@@ -46,8 +64,14 @@ fn test_instantiation() {
         StateResponse {
             quote_asset_reserve: Uint128::from(100u128),
             base_asset_reserve: Uint128::from(10_000u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::zero(),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -62,14 +86,28 @@ fn test_update_config() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: Uint128::from(100u128),
         base_asset_reserve: Uint128::from(10_000u128),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
 
     // Update the config
     let msg = ExecuteMsg::UpdateConfig {
-        owner: "addr0001".to_string(),
+        owner: Some("addr0001".to_string()),
+        margin_engine: None,
+        pricefeed: None,
+        fluctuation_limit_ratio: None,
+        max_holding_base_asset: None,
+        open_interest_notional_cap: None,
+        toll_ratio: None,
+        spread_ratio: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -83,8 +121,335 @@ fn test_update_config() {
             owner: Addr::unchecked("addr0001".to_string()),
             quote_asset: "ETH".to_string(),
             base_asset: "USD".to_string(),
+            pricefeed: Addr::unchecked("oracle".to_string()),
+            margin_engine: Some(Addr::unchecked("addr0000".to_string())),
+            fluctuation_limit_ratio: Uint128::zero(),
+            max_holding_base_asset: Uint128::zero(),
+            open_interest_notional_cap: Uint128::zero(),
+            toll_ratio: Uint128::zero(),
+            spread_ratio: Uint128::zero(),
         }
     );
+
+    // Update should fail as addr0000 is no longer the owner
+    let msg = ExecuteMsg::UpdateConfig {
+        owner: None,
+        margin_engine: Some("addr0002".to_string()),
+        pricefeed: None,
+        fluctuation_limit_ratio: None,
+        max_holding_base_asset: None,
+        open_interest_notional_cap: None,
+        toll_ratio: None,
+        spread_ratio: None,
+    };
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert!(result.is_err());
+
+    // Update the margin engine
+    let msg = ExecuteMsg::UpdateConfig {
+        owner: None,
+        margin_engine: Some("addr0002".to_string()),
+        pricefeed: None,
+        fluctuation_limit_ratio: None,
+        max_holding_base_asset: None,
+        open_interest_notional_cap: None,
+        toll_ratio: None,
+        spread_ratio: None,
+    };
+
+    let info = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.margin_engine, Some(Addr::unchecked("addr0002".to_string())));
+
+    // Update the pricefeed, fluctuation limit ratio and open interest limits
+    let msg = ExecuteMsg::UpdateConfig {
+        owner: None,
+        margin_engine: None,
+        pricefeed: Some("pricefeed".to_string()),
+        fluctuation_limit_ratio: Some(Uint128::from(100_000_000u128)),
+        max_holding_base_asset: Some(to_decimals(50)),
+        open_interest_notional_cap: Some(to_decimals(10_000)),
+        toll_ratio: None,
+        spread_ratio: None,
+    };
+
+    let info = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.pricefeed, Addr::unchecked("pricefeed"));
+    assert_eq!(config.fluctuation_limit_ratio, Uint128::from(100_000_000u128));
+    assert_eq!(config.max_holding_base_asset, to_decimals(50));
+    assert_eq!(config.open_interest_notional_cap, to_decimals(10_000));
+}
+
+#[test]
+fn test_swap_not_margin_engine() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0001", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, swap_msg);
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    let swap_msg = ExecuteMsg::SwapOutput {
+        direction: Direction::AddToAmm,
+        base_asset_amount: to_decimals(150),
+        quote_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0001", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, swap_msg);
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    // reserves are untouched
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, to_decimals(1000));
+    assert_eq!(state.base_asset_reserve, to_decimals(100));
+}
+
+#[test]
+fn test_swap_limits() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // long receives 37.5 base
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: Some(to_decimals(40)),
+    };
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, swap_msg);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Swap returns 37500000000 which is less than the limit of 40000000000"
+    );
+
+    // short pays 150 base
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::RemoveFromAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: Some(to_decimals(100)),
+    };
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, swap_msg);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Swap costs 150000000000 which is more than the limit of 100000000000"
+    );
+
+    // adding 150 base receives 600 quote
+    let swap_msg = ExecuteMsg::SwapOutput {
+        direction: Direction::AddToAmm,
+        base_asset_amount: to_decimals(150),
+        quote_asset_amount_limit: Some(to_decimals(700)),
+    };
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, swap_msg);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Swap returns 600000000000 which is less than the limit of 700000000000"
+    );
+
+    // removing 50 base costs 1000 quote
+    let swap_msg = ExecuteMsg::SwapOutput {
+        direction: Direction::RemoveFromAmm,
+        base_asset_amount: to_decimals(50),
+        quote_asset_amount_limit: Some(to_decimals(900)),
+    };
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, swap_msg);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Swap costs 1000000000000 which is more than the limit of 900000000000"
+    );
+
+    // a limit that is met goes through
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: Some(Uint128::from(37_500_000_000u128)),
+    };
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, to_decimals(1_600));
+    assert_eq!(state.base_asset_reserve, Uint128::from(62_500_000_000u128));
+}
+
+#[test]
+fn test_swap_over_fluctuation_limit() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::from(100_000_000u128), // 10%
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    let mut env = mock_env();
+    instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    // the first swap of the block moves the price from 10 to 10.40
+    env.block.height += 1;
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(20),
+        base_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env.clone(), info, swap_msg).unwrap();
+
+    // the second moves it to 10.81, which is still within 10%
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(20),
+        base_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env.clone(), info, swap_msg).unwrap();
+
+    // the third would move it to 11.23, so is over the limit for the block
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(20),
+        base_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), env.clone(), info, swap_msg.clone());
+    assert_eq!(result.unwrap_err().to_string(), "Price is over the fluctuation limit");
+
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, to_decimals(1_040));
+
+    // swapping the other way is checked against the same price
+    let swap_msg_output = ExecuteMsg::SwapOutput {
+        direction: Direction::AddToAmm,
+        base_asset_amount: to_decimals(20),
+        quote_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), env.clone(), info, swap_msg_output);
+    assert_eq!(result.unwrap_err().to_string(), "Price is over the fluctuation limit");
+
+    // in the next block the price starts at 10.81 so the swap is fine
+    env.block.height += 1;
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env, info, swap_msg).unwrap();
+}
+
+#[test]
+fn test_swap_input_long_18_decimals() {
+    let decimals = Uint128::from(1_000_000_000_000_000_000u128);
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 18u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: Uint128::from(10_000_000u128) * decimals,
+        base_asset_reserve: Uint128::from(5_000u128) * decimals,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, Uint128::from(2_000u128) * decimals);
+
+    // Swap in USD
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: Uint128::from(50_000u128) * decimals,
+        base_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0000", &[]);
+    let res = execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+    assert_eq!(res.attributes[2].value, "24875621890547263681");
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, Uint128::from(10_050_000u128) * decimals);
+    assert_eq!(state.base_asset_reserve, Uint128::from(4_975_124_378_109_452_736_319u128));
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, Uint128::from(2_020_049_999_999_999_999_999u128));
 }
 
 #[test]
@@ -96,7 +461,14 @@ fn test_swap_input_long() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -105,6 +477,7 @@ fn test_swap_input_long() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -116,8 +489,14 @@ fn test_swap_input_long() {
         StateResponse {
             quote_asset_reserve: to_decimals(1_600),
             base_asset_reserve: Uint128::from(62_500_000_000u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::from(Uint128::from(37_500_000_000u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -132,7 +511,14 @@ fn test_swap_input_short() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -141,6 +527,7 @@ fn test_swap_input_short() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::RemoveFromAmm,
         quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -152,8 +539,14 @@ fn test_swap_input_short() {
         StateResponse {
             quote_asset_reserve: to_decimals(400),
             base_asset_reserve: to_decimals(250),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: -SignedDecimal::from(to_decimals(150)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -168,7 +561,14 @@ fn test_swap_output_short() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -177,6 +577,7 @@ fn test_swap_output_short() {
     let swap_msg = ExecuteMsg::SwapOutput {
         direction: Direction::AddToAmm,
         base_asset_amount: to_decimals(150),
+        quote_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -188,8 +589,14 @@ fn test_swap_output_short() {
         StateResponse {
             quote_asset_reserve: to_decimals(400),
             base_asset_reserve: to_decimals(250),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: -SignedDecimal::from(to_decimals(150)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -204,7 +611,14 @@ fn test_swap_output_long() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -213,6 +627,7 @@ fn test_swap_output_long() {
     let swap_msg = ExecuteMsg::SwapOutput {
         direction: Direction::RemoveFromAmm,
         base_asset_amount: to_decimals(50),
+        quote_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -224,8 +639,14 @@ fn test_swap_output_long() {
         StateResponse {
             quote_asset_reserve: to_decimals(2_000),
             base_asset_reserve: to_decimals(50),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::from(to_decimals(50)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -240,7 +661,14 @@ fn test_swap_input_short_long() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -249,6 +677,7 @@ fn test_swap_input_short_long() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::RemoveFromAmm,
         quote_asset_amount: to_decimals(480),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -261,8 +690,14 @@ fn test_swap_input_short_long() {
         StateResponse {
             quote_asset_reserve: to_decimals(520),
             base_asset_reserve: Uint128::from(192_307_692_308u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: -SignedDecimal::from(Uint128::from(92_307_692_308u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -271,6 +706,7 @@ fn test_swap_input_short_long() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: to_decimals(960),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -282,8 +718,14 @@ fn test_swap_input_short_long() {
         StateResponse {
             quote_asset_reserve: to_decimals(1_480),
             base_asset_reserve: Uint128::from(67_567_567_568u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::from(Uint128::from(32_432_432_432u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -298,7 +740,14 @@ fn test_swap_input_short_long_long() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -306,6 +755,7 @@ fn test_swap_input_short_long_long() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::RemoveFromAmm,
         quote_asset_amount: to_decimals(200),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -318,8 +768,14 @@ fn test_swap_input_short_long_long() {
         StateResponse {
             quote_asset_reserve: to_decimals(800),
             base_asset_reserve: to_decimals(125),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: -SignedDecimal::from(to_decimals(25)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -327,6 +783,7 @@ fn test_swap_input_short_long_long() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: to_decimals(100),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -339,8 +796,14 @@ fn test_swap_input_short_long_long() {
         StateResponse {
             quote_asset_reserve: to_decimals(900),
             base_asset_reserve: Uint128::from(111_111_111_112u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: -SignedDecimal::from(Uint128::from(11_111_111_112u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -348,6 +811,7 @@ fn test_swap_input_short_long_long() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: to_decimals(200),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -361,8 +825,14 @@ fn test_swap_input_short_long_long() {
         StateResponse {
             quote_asset_reserve: to_decimals(1100),
             base_asset_reserve: Uint128::from(90_909_090_910u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::from(Uint128::from(9_090_909_090u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -377,7 +847,14 @@ fn test_swap_input_short_long_short() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -385,6 +862,7 @@ fn test_swap_input_short_long_short() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::RemoveFromAmm,
         quote_asset_amount: to_decimals(200),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -397,8 +875,14 @@ fn test_swap_input_short_long_short() {
         StateResponse {
             quote_asset_reserve: to_decimals(800),
             base_asset_reserve: to_decimals(125),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: -SignedDecimal::from(to_decimals(25)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -406,6 +890,7 @@ fn test_swap_input_short_long_short() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: to_decimals(450),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -418,8 +903,14 @@ fn test_swap_input_short_long_short() {
         StateResponse {
             quote_asset_reserve: to_decimals(1250),
             base_asset_reserve: to_decimals(80),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::from(to_decimals(20)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -427,6 +918,7 @@ fn test_swap_input_short_long_short() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::RemoveFromAmm,
         quote_asset_amount: to_decimals(250),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -440,8 +932,14 @@ fn test_swap_input_short_long_short() {
         StateResponse {
             quote_asset_reserve: to_decimals(1000),
             base_asset_reserve: to_decimals(100),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::zero(),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -456,7 +954,14 @@ fn test_swap_input_long_integration_example() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: Uint128::from(1_000_000_000_000u128),
         base_asset_reserve: Uint128::from(100_000_000_000u128),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -465,6 +970,7 @@ fn test_swap_input_long_integration_example() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: Uint128::from(600_000_000_000u128), // this is swapping 60 at 10x leverage
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -476,8 +982,14 @@ fn test_swap_input_long_integration_example() {
         StateResponse {
             quote_asset_reserve: Uint128::from(1_600_000_000_000u128),
             base_asset_reserve: Uint128::from(62_500_000_000u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::from(Uint128::from(37_500_000_000u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: Uint128::from(1_000_000_000u128),
         }
     );
@@ -492,7 +1004,14 @@ fn test_swap_input_long_short_integration_example() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: Uint128::from(1_000_000_000_000u128),
         base_asset_reserve: Uint128::from(100_000_000_000u128),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -501,6 +1020,7 @@ fn test_swap_input_long_short_integration_example() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: Uint128::from(600_000_000_000u128), // this is swapping 60 at 10x leverage
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -512,8 +1032,14 @@ fn test_swap_input_long_short_integration_example() {
         StateResponse {
             quote_asset_reserve: Uint128::from(1_600_000_000_000u128),
             base_asset_reserve: Uint128::from(62_500_000_000u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::from(Uint128::from(37_500_000_000u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: Uint128::from(1_000_000_000u128),
         }
     );
@@ -522,6 +1048,7 @@ fn test_swap_input_long_short_integration_example() {
     let swap_msg = ExecuteMsg::SwapOutput {
         direction: Direction::AddToAmm,
         base_asset_amount: Uint128::from(37_500_000_000u128), // this is swapping 60 at 10x leverage
+        quote_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -533,8 +1060,14 @@ fn test_swap_input_long_short_integration_example() {
         StateResponse {
             quote_asset_reserve: Uint128::from(1_000_000_000_000u128),
             base_asset_reserve: Uint128::from(100_000_000_000u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::zero(),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: Uint128::from(1_000_000_000u128),
         }
     );
@@ -549,7 +1082,14 @@ fn test_swap_input_twice_short_long() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -558,6 +1098,7 @@ fn test_swap_input_twice_short_long() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::RemoveFromAmm,
         quote_asset_amount: to_decimals(10),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -567,6 +1108,7 @@ fn test_swap_input_twice_short_long() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: to_decimals(10),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -578,8 +1120,14 @@ fn test_swap_input_twice_short_long() {
         StateResponse {
             quote_asset_reserve: to_decimals(1_000),
             base_asset_reserve: Uint128::from(100_000_000_001u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: -SignedDecimal::from(Uint128::from(1u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -594,7 +1142,14 @@ fn test_swap_input_twice_long_short() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -603,6 +1158,7 @@ fn test_swap_input_twice_long_short() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::AddToAmm,
         quote_asset_amount: to_decimals(10),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -612,6 +1168,7 @@ fn test_swap_input_twice_long_short() {
     let swap_msg = ExecuteMsg::SwapInput {
         direction: Direction::RemoveFromAmm,
         quote_asset_amount: to_decimals(10),
+        base_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -623,8 +1180,14 @@ fn test_swap_input_twice_long_short() {
         StateResponse {
             quote_asset_reserve: to_decimals(1_000),
             base_asset_reserve: Uint128::from(100_000_000_001u128),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: -SignedDecimal::from(Uint128::from(1u128)),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -639,7 +1202,14 @@ fn test_swap_output_twice_short_long() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -648,6 +1218,7 @@ fn test_swap_output_twice_short_long() {
     let swap_msg = ExecuteMsg::SwapOutput {
         direction: Direction::RemoveFromAmm,
         base_asset_amount: to_decimals(10),
+        quote_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -657,6 +1228,7 @@ fn test_swap_output_twice_short_long() {
     let swap_msg = ExecuteMsg::SwapOutput {
         direction: Direction::AddToAmm,
         base_asset_amount: to_decimals(10),
+        quote_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -668,8 +1240,14 @@ fn test_swap_output_twice_short_long() {
         StateResponse {
             quote_asset_reserve: Uint128::from(1_000_000_000_001u128),
             base_asset_reserve: to_decimals(100),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::zero(),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
@@ -684,7 +1262,14 @@ fn test_swap_output_twice_long_short() {
         base_asset: "USD".to_string(),
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
-        funding_period: 3_600 as u64,
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -693,6 +1278,7 @@ fn test_swap_output_twice_long_short() {
     let swap_msg = ExecuteMsg::SwapOutput {
         direction: Direction::AddToAmm,
         base_asset_amount: to_decimals(10),
+        quote_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -702,6 +1288,7 @@ fn test_swap_output_twice_long_short() {
     let swap_msg = ExecuteMsg::SwapOutput {
         direction: Direction::RemoveFromAmm,
         base_asset_amount: to_decimals(10),
+        quote_asset_amount_limit: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -713,9 +1300,888 @@ fn test_swap_output_twice_long_short() {
         StateResponse {
             quote_asset_reserve: Uint128::from(1_000_000_000_001u128),
             base_asset_reserve: to_decimals(100),
-            funding_rate: Uint128::zero(),
-            funding_period: 3_600 as u64,
+            funding_rate: SignedDecimal::zero(),
+            funding_period: 3_600_u64,
+            next_funding_time: 1_571_801_019_u64,
+            cumulative_premium_fraction: SignedDecimal::zero(),
+            total_position_size: SignedDecimal::zero(),
+            open: true,
+            shutdown: false,
+            settlement_price: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
         }
     );
-}
\ No newline at end of file
+}
+#[test]
+fn test_repeg() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // traders are net long 37.5
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+
+    // only the owner can repeg
+    let msg = ExecuteMsg::Repeg {
+        target_price: to_decimals(30),
+    };
+    let info = mock_info("addr0001", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    let msg = ExecuteMsg::Repeg {
+        target_price: Uint128::zero(),
+    };
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Price must be greater than zero");
+
+    // the longs could close for 600 before and 703.125 after, so the
+    // protocol pays the difference
+    let msg = ExecuteMsg::Repeg {
+        target_price: to_decimals(30),
+    };
+    let info = mock_info("addr0000", &[]);
+    let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    let cost: SignedDecimal = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(cost, SignedDecimal::from(Uint128::from(103_125_000_000u128)));
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, to_decimals(1_875));
+    assert_eq!(state.base_asset_reserve, Uint128::from(62_500_000_000u128));
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(30));
+
+    // the repeg changes k so positions opened before it can be told apart
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::LiquidityHistory {
+        start_after: Some(0),
+        limit: None,
+    }).unwrap();
+    let history: Vec<LiquidityChangeResponse> = from_binary(&res).unwrap();
+    assert_eq!(
+        history,
+        vec![LiquidityChangeResponse {
+            index: 1,
+            invariant_k: Uint128::from(117_187_500_000_000u128),
+            cumulative_notional: SignedDecimal::from(to_decimals(600)),
+            timestamp: mock_env().block.time,
+        }],
+    );
+}
+
+#[test]
+fn test_repeg_net_short() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // traders are net short 150
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::RemoveFromAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+
+    // the shorts could close for 600 before and 750 after, so the protocol
+    // gains the difference
+    let msg = ExecuteMsg::Repeg {
+        target_price: to_decimals(2),
+    };
+    let info = mock_info("addr0000", &[]);
+    let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    let cost: SignedDecimal = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(cost, -SignedDecimal::from(to_decimals(150)));
+    assert_eq!(res.attributes[2].value, "-150000000000");
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, to_decimals(500));
+    assert_eq!(state.base_asset_reserve, to_decimals(250));
+}
+
+#[test]
+fn test_adjust_k() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0001".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // traders pay 250 for a long
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(250),
+        base_asset_amount_limit: None,
+    };
+
+    let info = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+
+    // only the owner or margin engine can adjust k
+    let msg = ExecuteMsg::AdjustK {
+        multiplier: to_decimals(2),
+    };
+    let info = mock_info("addr0002", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    let msg = ExecuteMsg::AdjustK {
+        multiplier: Uint128::zero(),
+    };
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Multiplier must be greater than zero");
+
+    // double the reserves
+    let msg = ExecuteMsg::AdjustK {
+        multiplier: to_decimals(2),
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, to_decimals(2_500));
+    assert_eq!(state.base_asset_reserve, to_decimals(160));
+
+    // the price is unchanged
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, Uint128::from(15_625_000_000u128));
+
+    // and halve them again from the engine
+    let msg = ExecuteMsg::AdjustK {
+        multiplier: Uint128::from(500_000_000u128),
+    };
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(60);
+    let info = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::LiquidityHistoryLength {}).unwrap();
+    let length: u64 = from_binary(&res).unwrap();
+    assert_eq!(length, 3);
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::LiquidityHistory {
+        start_after: None,
+        limit: None,
+    }).unwrap();
+    let history: Vec<LiquidityChangeResponse> = from_binary(&res).unwrap();
+    assert_eq!(
+        history,
+        vec![
+            LiquidityChangeResponse {
+                index: 0,
+                invariant_k: to_decimals(100_000),
+                cumulative_notional: SignedDecimal::zero(),
+                timestamp: mock_env().block.time,
+            },
+            LiquidityChangeResponse {
+                index: 1,
+                invariant_k: to_decimals(400_000),
+                cumulative_notional: SignedDecimal::from(to_decimals(250)),
+                timestamp: mock_env().block.time,
+            },
+            LiquidityChangeResponse {
+                index: 2,
+                invariant_k: to_decimals(100_000),
+                cumulative_notional: SignedDecimal::from(to_decimals(250)),
+                timestamp: env.block.time,
+            },
+        ]
+    );
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::LiquidityHistory {
+        start_after: Some(0),
+        limit: Some(1),
+    }).unwrap();
+    let history: Vec<LiquidityChangeResponse> = from_binary(&res).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].index, 1);
+}
+
+#[test]
+fn test_set_open() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // only the owner can close the market
+    let msg = ExecuteMsg::SetOpen {
+        open: false,
+    };
+    let info = mock_info("addr0001", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    let msg = ExecuteMsg::SetOpen {
+        open: false,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert!(!state.open);
+
+    // swaps fail while the market is closed
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, swap_msg.clone());
+    assert_eq!(result.unwrap_err().to_string(), "Market is closed");
+
+    let msg = ExecuteMsg::SwapOutput {
+        direction: Direction::AddToAmm,
+        base_asset_amount: to_decimals(10),
+        quote_asset_amount_limit: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, msg);
+    assert_eq!(result.unwrap_err().to_string(), "Market is closed");
+
+    // reopening the market starts a new funding period
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(7_200);
+    let msg = ExecuteMsg::SetOpen {
+        open: true,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert!(state.open);
+    assert_eq!(state.next_funding_time, env.block.time.plus_seconds(3_600).seconds());
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+}
+
+#[test]
+fn test_shutdown() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // traders are net long 37.5
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg.clone()).unwrap();
+
+    // only the owner can shut the market down
+    let info = mock_info("addr0001", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Shutdown {});
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    // the net position was opened for 600, so it settles at 600 / 37.5 = 16
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Shutdown {}).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert!(!state.open);
+    assert!(state.shutdown);
+    assert_eq!(state.settlement_price, to_decimals(16));
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, swap_msg);
+    assert_eq!(result.unwrap_err().to_string(), "Market is closed");
+}
+
+#[test]
+fn test_shutdown_no_net_position() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // with no net position the market settles at the spot price
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Shutdown {}).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.settlement_price, to_decimals(10));
+}
+
+#[test]
+fn test_settle_funding() {
+    let mut deps = mock_querier::mock_dependencies(&[]);
+    deps.querier.with_underlying_price(Uint128::from(9_500_000_000u128));
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // funding can't be settled before the funding period has elapsed
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::SettleFunding {});
+    assert!(result.is_err());
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(3_600);
+
+    // only the margin engine can settle funding
+    let info = mock_info("addr0001", &[]);
+    let result = execute(deps.as_mut(), env.clone(), info, ExecuteMsg::SettleFunding {});
+    assert_eq!(result.unwrap_err().to_string(), "Unauthorized");
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env, info, ExecuteMsg::SettleFunding {}).unwrap();
+
+    // premium = 10 - 9.5 = 0.5
+    // premium fraction = 0.5 * 3600 / 86400 = 0.0208333...
+    // funding rate = 0.0208333 / 9.5 = 0.0021929...
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.funding_rate, SignedDecimal::from(Uint128::from(2_192_982u128)));
+    assert_eq!(state.cumulative_premium_fraction, SignedDecimal::from(Uint128::from(20_833_333u128)));
+    assert_eq!(state.next_funding_time, 1_571_804_619_u64);
+
+    // funding can't be settled twice in the same period
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(3_600);
+
+    let info = mock_info("addr0000", &[]);
+    let result = execute(deps.as_mut(), env, info, ExecuteMsg::SettleFunding {});
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_settle_funding_negative() {
+    let mut deps = mock_querier::mock_dependencies(&[]);
+    deps.querier.with_underlying_price(Uint128::from(10_500_000_000u128));
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(3_600);
+
+    let info = mock_info("addr0000", &[]);
+    let res = execute(deps.as_mut(), env, info, ExecuteMsg::SettleFunding {}).unwrap();
+    assert_eq!(res.attributes[1].value, "-20833333");
+
+    // premium = 10 - 10.5 = -0.5, so shorts pay longs
+    // premium fraction = -0.5 * 3600 / 86400 = -0.0208333...
+    // funding rate = -0.0208333 / 10.5 = -0.0019841...
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.funding_rate, -SignedDecimal::from(Uint128::from(1_984_126u128)));
+    assert_eq!(state.cumulative_premium_fraction, -SignedDecimal::from(Uint128::from(20_833_333u128)));
+}
+
+#[test]
+fn test_settle_funding_late() {
+    let mut deps = mock_querier::mock_dependencies(&[]);
+    deps.querier.with_underlying_price(to_decimals(10));
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // settle three periods late
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(4 * 3_600);
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env, info, ExecuteMsg::SettleFunding {}).unwrap();
+
+    // mark and index are equal so no funding is due, and the next
+    // settlement is half a period from now
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.funding_rate, SignedDecimal::zero());
+    assert_eq!(state.cumulative_premium_fraction, SignedDecimal::zero());
+    assert_eq!(state.next_funding_time, 1_571_813_619_u64);
+}
+
+#[test]
+fn test_settle_funding_without_recent_trades() {
+    let mut deps = mock_querier::mock_dependencies(&[]);
+    deps.querier.with_underlying_price(to_decimals(10));
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // the only trade moves the price from 10 to 25.6 ten seconds in
+    let mut env = mock_env();
+    env.block.height += 1;
+    env.block.time = env.block.time.plus_seconds(10);
+
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env, info, swap_msg).unwrap();
+
+    // nothing trades in the funding period so the mark price is 25.6 for
+    // all of it, (25.6 - 10) / 24 = 0.65
+    let mut env = mock_env();
+    env.block.height += 2;
+    env.block.time = env.block.time.plus_seconds(2 * 3_600);
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env, info, ExecuteMsg::SettleFunding {}).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.cumulative_premium_fraction, SignedDecimal::from(Uint128::new(650_000_000)));
+}
+
+#[test]
+fn test_twap_price() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // price moves from 10 to 25.6 ten seconds later
+    let mut env = mock_env();
+    env.block.height += 1;
+    env.block.time = env.block.time.plus_seconds(10);
+
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env.clone(), info, swap_msg).unwrap();
+
+    let mut env = mock_env();
+    env.block.height += 2;
+    env.block.time = env.block.time.plus_seconds(20);
+
+    // no interval is the latest price
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::Twap { interval: 0 }).unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(25_600_000_000u128));
+
+    // (25.6 * 10 + 10 * 10) / 20 = 17.8
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::Twap { interval: 20 }).unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(17_800_000_000u128));
+
+    // (25.6 * 10 + 10 * 5) / 15 = 20.4
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::Twap { interval: 15 }).unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(20_400_000_000u128));
+
+    // history is shorter than the interval so we average what we have
+    let res = query(deps.as_ref(), env, QueryMsg::Twap { interval: 100 }).unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(17_800_000_000u128));
+
+    // the latest snapshot is older than the interval so it is the price for
+    // all of it
+    let mut env = mock_env();
+    env.block.height += 2;
+    env.block.time = env.block.time.plus_seconds(2_000);
+
+    let res = query(deps.as_ref(), env, QueryMsg::Twap { interval: 900 }).unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(25_600_000_000u128));
+}
+
+#[test]
+fn test_twap_price_same_block() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let mut env = mock_env();
+    env.block.height += 1;
+    env.block.time = env.block.time.plus_seconds(10);
+
+    // two swaps in the same block only leave the latest snapshot
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(250),
+        base_asset_amount_limit: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env.clone(), info, swap_msg).unwrap();
+
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::RemoveFromAmm,
+        quote_asset_amount: to_decimals(250),
+        base_asset_amount_limit: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env.clone(), info, swap_msg).unwrap();
+
+    let mut env = mock_env();
+    env.block.height += 2;
+    env.block.time = env.block.time.plus_seconds(20);
+
+    // (10 * 10 + 10 * 10) / 20 = 10
+    let res = query(deps.as_ref(), env, QueryMsg::Twap { interval: 20 }).unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, to_decimals(10));
+}
+
+#[test]
+fn test_spot_price() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(10));
+
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(600),
+        base_asset_amount_limit: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+
+    // 1600 / 62.5 = 25.6
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, Uint128::from(25_600_000_000u128));
+}
+
+#[test]
+fn test_underlying_price() {
+    let mut deps = mock_querier::mock_dependencies(&[]);
+    deps.querier.with_underlying_price(Uint128::from(9_500_000_000u128));
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // the index price comes from the pricefeed
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::UnderlyingPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, Uint128::from(9_500_000_000u128));
+}
+
+#[test]
+fn test_input_and_output_price_queries() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::InputPrice {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(50),
+    }).unwrap();
+    let amount: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(amount, Uint128::from(4_761_904_761u128));
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::OutputPrice {
+        direction: Direction::RemoveFromAmm,
+        base_asset_amount: to_decimals(5),
+    }).unwrap();
+    let amount: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(amount, Uint128::from(52_631_578_948u128));
+
+    // a zero amount is quoted as zero
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::InputPrice {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: Uint128::zero(),
+    }).unwrap();
+    let amount: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(amount, Uint128::zero());
+
+    // quoting doesn't move the reserves
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, to_decimals(1000));
+    assert_eq!(state.base_asset_reserve, to_decimals(100));
+}
+
+#[test]
+fn test_calc_fee() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        fluctuation_limit_ratio: Uint128::zero(),
+        max_holding_base_asset: Uint128::zero(),
+        open_interest_notional_cap: Uint128::zero(),
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        pricefeed: "oracle".to_string(),
+        margin_engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // trades are free by default
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::CalcFee {
+        quote_asset_amount: to_decimals(600),
+    }).unwrap();
+    let fee: CalcFeeResponse = from_binary(&res).unwrap();
+    assert_eq!(fee, CalcFeeResponse {
+        toll_fee: Uint128::zero(),
+        spread_fee: Uint128::zero(),
+    });
+
+    // a toll of 1% and spread of 0.5%
+    let msg = ExecuteMsg::UpdateConfig {
+        owner: None,
+        margin_engine: None,
+        pricefeed: None,
+        fluctuation_limit_ratio: None,
+        max_holding_base_asset: None,
+        open_interest_notional_cap: None,
+        toll_ratio: Some(Uint128::from(10_000_000u128)),
+        spread_ratio: Some(Uint128::from(5_000_000u128)),
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::CalcFee {
+        quote_asset_amount: to_decimals(600),
+    }).unwrap();
+    let fee: CalcFeeResponse = from_binary(&res).unwrap();
+    assert_eq!(fee, CalcFeeResponse {
+        toll_fee: to_decimals(6),
+        spread_fee: to_decimals(3),
+    });
+}
@@ -1,2 +1,5 @@
 pub mod margined_engine;
-pub mod margined_vamm;
\ No newline at end of file
+pub mod margined_insurance_fund;
+pub mod margined_pricefeed;
+pub mod margined_vamm;
+pub mod signed_decimal;
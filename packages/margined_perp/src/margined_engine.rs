@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use cosmwasm_std::{Addr, Timestamp, Uint128};
 use cw20::Cw20ReceiveMsg;
 
+use crate::signed_decimal::SignedDecimal;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Side {
@@ -19,13 +21,26 @@ pub enum PNLCalc {
     ORACLE
 }
 
+// what happens to bad debt the insurance fund can't cover
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BadDebtMode {
+    PAUSE,
+    SOCIALIZE,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     pub decimals: u8,
     pub eligible_collateral: String,
+    pub insurance_fund: String,
+    pub fee_pool: String,
     pub initial_margin_ratio: Uint128,
     pub maintenance_margin_ratio: Uint128,
     pub liquidation_fee: Uint128,
+    pub partial_liquidation_ratio: Uint128,
+    pub spread_limit_ratio: Uint128,
+    pub bad_debt_mode: BadDebtMode,
     pub vamm: Vec<String>,
 }
 
@@ -34,21 +49,43 @@ pub struct InstantiateMsg {
 pub enum ExecuteMsg {
     Receive(Cw20ReceiveMsg),
     UpdateConfig{
-        owner: String,
+        owner: Option<String>,
+        partial_liquidation_ratio: Option<Uint128>,
+        spread_limit_ratio: Option<Uint128>,
+        bad_debt_mode: Option<BadDebtMode>,
     },
     OpenPosition {
         vamm: String,
         side: Side,
         quote_asset_amount: Uint128,
         leverage: Uint128,
+        base_asset_amount_limit: Option<Uint128>,
     },
     ClosePosition {
         vamm: String,
+        quote_asset_amount_limit: Option<Uint128>,
+    },
+    SettlePosition {
+        vamm: String,
+    },
+    Liquidate {
+        vamm: String,
+        trader: String,
+    },
+    AddMargin {
+        vamm: String,
+        amount: Uint128,
+    },
+    RemoveMargin {
+        vamm: String,
+        amount: Uint128,
+    },
+    PayFunding {
+        vamm: String,
+    },
+    Unpause {
+        vamm: String,
     },
-    // Liquidate {},
-    // PayFunding {},
-    // DepositMargin {},
-    // WithdrawMargin {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -59,6 +96,11 @@ pub enum Cw20HookMsg {
         vamm: String,
         side: Side,
         leverage: Uint128,
+        base_asset_amount_limit: Option<Uint128>,
+    },
+    // allows you to add margin to a position and directly transfer funds
+    AddMargin {
+        vamm: String,
     },
 }
 
@@ -73,21 +115,40 @@ pub enum QueryMsg {
     TraderBalance {
         trader: String,
     },
-    // MarginRatio {},
+    MarginRatio {
+        vamm: String,
+        trader: String,
+        calc: PNLCalc,
+    },
+    BadDebt {
+        vamm: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ConfigResponse {
     pub owner: Addr,
     pub eligible_collateral: Addr,
+    pub insurance_fund: Addr,
+    pub fee_pool: Addr,
+    pub partial_liquidation_ratio: Uint128,
+    pub spread_limit_ratio: Uint128,
+    pub bad_debt_mode: BadDebtMode,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct PositionResponse {
-    pub size: Uint128,
+    pub size: SignedDecimal,
     pub margin: Uint128,
     pub notional: Uint128,
-    pub premium_fraction: Uint128,
+    pub premium_fraction: SignedDecimal,
     pub liquidity_history_index: Uint128,
     pub timestamp: Timestamp,
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BadDebtResponse {
+    pub total: Uint128,
+    pub socialized_loss: Uint128,
+    pub paused: bool,
+}
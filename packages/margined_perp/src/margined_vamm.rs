@@ -1,7 +1,9 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Timestamp, Uint128};
+
+use crate::signed_decimal::SignedDecimal;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
@@ -18,6 +20,13 @@ pub struct InstantiateMsg {
     pub quote_asset_reserve: Uint128,
     pub base_asset_reserve: Uint128,
     pub funding_period: u64,
+    pub fluctuation_limit_ratio: Uint128,
+    pub max_holding_base_asset: Uint128,
+    pub open_interest_notional_cap: Uint128,
+    pub toll_ratio: Uint128,
+    pub spread_ratio: Uint128,
+    pub pricefeed: String,
+    pub margin_engine: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -26,14 +35,34 @@ pub enum ExecuteMsg {
     SwapInput {
         direction: Direction,
         quote_asset_amount: Uint128,
+        base_asset_amount_limit: Option<Uint128>,
     },
     SwapOutput {
         direction: Direction,
         base_asset_amount: Uint128,
+        quote_asset_amount_limit: Option<Uint128>,
     },
     UpdateConfig {
-        owner: String,
+        owner: Option<String>,
+        margin_engine: Option<String>,
+        pricefeed: Option<String>,
+        fluctuation_limit_ratio: Option<Uint128>,
+        max_holding_base_asset: Option<Uint128>,
+        open_interest_notional_cap: Option<Uint128>,
+        toll_ratio: Option<Uint128>,
+        spread_ratio: Option<Uint128>,
+    },
+    SettleFunding {},
+    Repeg {
+        target_price: Uint128,
+    },
+    AdjustK {
+        multiplier: Uint128,
     },
+    SetOpen {
+        open: bool,
+    },
+    Shutdown {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -41,6 +70,27 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     Config {},
     State {},
+    Twap {
+        interval: u64,
+    },
+    SpotPrice {},
+    UnderlyingPrice {},
+    InputPrice {
+        direction: Direction,
+        quote_asset_amount: Uint128,
+    },
+    OutputPrice {
+        direction: Direction,
+        base_asset_amount: Uint128,
+    },
+    LiquidityHistory {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    LiquidityHistoryLength {},
+    CalcFee {
+        quote_asset_amount: Uint128,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -48,14 +98,40 @@ pub struct ConfigResponse {
     pub owner: Addr,
     pub quote_asset: String,
     pub base_asset: String,
+    pub pricefeed: Addr,
+    pub margin_engine: Option<Addr>,
+    pub fluctuation_limit_ratio: Uint128,
+    pub max_holding_base_asset: Uint128,
+    pub open_interest_notional_cap: Uint128,
+    pub toll_ratio: Uint128,
+    pub spread_ratio: Uint128,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct StateResponse {
     pub quote_asset_reserve: Uint128,
     pub base_asset_reserve: Uint128,
-    pub funding_rate: Uint128,
+    pub funding_rate: SignedDecimal,
     pub decimals: Uint128,
     pub funding_period: u64,
+    pub next_funding_time: u64,
+    pub cumulative_premium_fraction: SignedDecimal,
+    pub total_position_size: SignedDecimal,
+    pub open: bool,
+    pub shutdown: bool,
+    pub settlement_price: Uint128,
+}
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidityChangeResponse {
+    pub index: u64,
+    pub invariant_k: Uint128,
+    pub cumulative_notional: SignedDecimal,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CalcFeeResponse {
+    pub toll_fee: Uint128,
+    pub spread_fee: Uint128,
 }
@@ -0,0 +1,285 @@
+use schemars::JsonSchema;
+use serde::{de, ser, Deserialize, Deserializer, Serialize};
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Neg;
+use std::str::FromStr;
+
+use cosmwasm_std::{StdError, StdResult, Uint128, Uint256};
+
+/// A signed amount that uses the same fixed point representation as the
+/// Uint128 amounts used throughout the protocol, i.e. it is scaled by the
+/// decimals of the vAMM. It is serialized as a string, e.g. "-1500000000".
+///
+/// Like Uint128 it is a scaled integer, multiplying doesn't rescale so the
+/// product of two scaled amounts should be taken with checked_multiply_ratio
+/// to divide out the decimals without overflowing.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct SignedDecimal {
+    value: Uint128,
+    negative: bool,
+}
+
+impl SignedDecimal {
+    /// Creates a new signed decimal, zero is always positive
+    pub fn new(value: Uint128, negative: bool) -> Self {
+        SignedDecimal {
+            value,
+            negative: negative && !value.is_zero(),
+        }
+    }
+
+    pub const fn zero() -> Self {
+        SignedDecimal {
+            value: Uint128::zero(),
+            negative: false,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.value.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_positive(&self) -> bool {
+        !self.negative && !self.value.is_zero()
+    }
+
+    /// Returns the absolute value as a Uint128
+    pub fn abs(&self) -> Uint128 {
+        self.value
+    }
+
+    pub fn checked_add(self, other: Self) -> StdResult<Self> {
+        if self.negative == other.negative {
+            return Ok(SignedDecimal::new(
+                self.value.checked_add(other.value)?,
+                self.negative,
+            ));
+        }
+
+        // the signs differ so the larger value keeps its sign
+        if self.value >= other.value {
+            Ok(SignedDecimal::new(self.value - other.value, self.negative))
+        } else {
+            Ok(SignedDecimal::new(other.value - self.value, other.negative))
+        }
+    }
+
+    pub fn checked_sub(self, other: Self) -> StdResult<Self> {
+        self.checked_add(-other)
+    }
+
+    pub fn checked_mul(self, other: Self) -> StdResult<Self> {
+        Ok(SignedDecimal::new(
+            self.value.checked_mul(other.value)?,
+            self.negative != other.negative,
+        ))
+    }
+
+    /// Returns self * numerator / denominator, the product is taken in 256 bits
+    /// so it only overflows if the result does. The result is truncated
+    /// towards zero.
+    pub fn checked_multiply_ratio(self, numerator: Self, denominator: Self) -> StdResult<Self> {
+        let value = self.value
+            .full_mul(numerator.value)
+            .checked_div(Uint256::from(denominator.value))?;
+
+        Ok(SignedDecimal::new(
+            Uint128::try_from(value)?,
+            self.negative != (numerator.negative != denominator.negative),
+        ))
+    }
+
+    /// Divides, the result is truncated towards zero as with Uint128
+    pub fn checked_div(self, other: Self) -> StdResult<Self> {
+        Ok(SignedDecimal::new(
+            self.value.checked_div(other.value)?,
+            self.negative != other.negative,
+        ))
+    }
+}
+
+impl From<Uint128> for SignedDecimal {
+    fn from(value: Uint128) -> Self {
+        SignedDecimal::new(value, false)
+    }
+}
+
+impl Neg for SignedDecimal {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        SignedDecimal::new(self.value, !self.negative)
+    }
+}
+
+impl Ord for SignedDecimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, false) => self.value.cmp(&other.value),
+            (true, true) => other.value.cmp(&self.value),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        }
+    }
+}
+
+impl PartialOrd for SignedDecimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for SignedDecimal {
+    type Err = StdError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match input.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, input.strip_prefix('+').unwrap_or(input)),
+        };
+
+        // the u128 parse takes a leading '+' so a second sign is caught here
+        let error = || StdError::generic_err(format!("Error parsing signed decimal '{}'", input));
+        if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(error());
+        }
+
+        let value = digits
+            .parse::<u128>()
+            .map_err(|_| error())?;
+
+        Ok(SignedDecimal::new(Uint128::from(value), negative))
+    }
+}
+
+impl fmt::Display for SignedDecimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", self.value)
+    }
+}
+
+impl Serialize for SignedDecimal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for SignedDecimal {
+    fn deserialize<D>(deserializer: D) -> Result<SignedDecimal, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(SignedDecimalVisitor)
+    }
+}
+
+struct SignedDecimalVisitor;
+
+impl<'de> de::Visitor<'de> for SignedDecimalVisitor {
+    type Value = SignedDecimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("string-encoded signed integer")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        SignedDecimal::from_str(v).map_err(|e| E::custom(e.to_string()))
+    }
+}
+
+impl JsonSchema for SignedDecimal {
+    fn schema_name() -> String {
+        "SignedDecimal".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::{from_slice, to_vec};
+
+    fn signed(value: i128) -> SignedDecimal {
+        SignedDecimal::new(Uint128::from(value.unsigned_abs()), value < 0)
+    }
+
+    #[test]
+    fn test_zero_is_not_negative() {
+        assert_eq!(SignedDecimal::new(Uint128::zero(), true), SignedDecimal::zero());
+        assert_eq!(-SignedDecimal::zero(), SignedDecimal::zero());
+        assert!(!SignedDecimal::zero().is_negative());
+        assert!(!SignedDecimal::zero().is_positive());
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        assert_eq!(signed(5).checked_add(signed(3)).unwrap(), signed(8));
+        assert_eq!(signed(5).checked_add(signed(-8)).unwrap(), signed(-3));
+        assert_eq!(signed(-5).checked_add(signed(5)).unwrap(), SignedDecimal::zero());
+        assert_eq!(signed(3).checked_sub(signed(5)).unwrap(), signed(-2));
+        assert_eq!(signed(-3).checked_sub(signed(-5)).unwrap(), signed(2));
+        assert_eq!(signed(-3).checked_mul(signed(5)).unwrap(), signed(-15));
+        assert_eq!(signed(-3).checked_mul(signed(-5)).unwrap(), signed(15));
+        assert_eq!(signed(-7).checked_div(signed(2)).unwrap(), signed(-3));
+        assert_eq!(signed(7).checked_div(signed(-7)).unwrap(), signed(-1));
+
+        assert_eq!(signed(-7).checked_multiply_ratio(signed(3), signed(2)).unwrap(), signed(-10));
+        assert_eq!(signed(-7).checked_multiply_ratio(signed(3), signed(-2)).unwrap(), signed(10));
+
+        // the product of two 18 decimal amounts is over 128 bits
+        let amount = SignedDecimal::from(Uint128::new(1_000_000_000_000_000_000_000));
+        let decimals = SignedDecimal::from(Uint128::new(1_000_000_000_000_000_000));
+        assert!(amount.checked_mul(amount).is_err());
+        assert_eq!(amount.checked_multiply_ratio(-amount, decimals).unwrap(), -amount.checked_mul(signed(1_000)).unwrap());
+
+        assert!(signed(1).checked_div(SignedDecimal::zero()).is_err());
+        assert!(signed(1).checked_multiply_ratio(signed(1), SignedDecimal::zero()).is_err());
+        assert!(SignedDecimal::from(Uint128::MAX).checked_add(signed(1)).is_err());
+        assert!(SignedDecimal::from(Uint128::MAX).checked_sub(signed(-1)).is_err());
+    }
+
+    #[test]
+    fn test_ordering() {
+        assert!(signed(-5) < signed(-3));
+        assert!(signed(-3) < SignedDecimal::zero());
+        assert!(SignedDecimal::zero() < signed(2));
+        assert!(signed(2) < signed(3));
+    }
+
+    #[test]
+    fn test_string_and_serde() {
+        assert_eq!(signed(-1_500).to_string(), "-1500");
+        assert_eq!(SignedDecimal::from_str("-1500").unwrap(), signed(-1_500));
+        assert_eq!(SignedDecimal::from_str("-0").unwrap(), SignedDecimal::zero());
+        assert!(SignedDecimal::from_str("1.5").is_err());
+        assert!(SignedDecimal::from_str("--1").is_err());
+        assert!(SignedDecimal::from_str("-+1").is_err());
+        assert!(SignedDecimal::from_str("+-1").is_err());
+        assert!(SignedDecimal::from_str("++1").is_err());
+        assert_eq!(SignedDecimal::from_str("+1").unwrap(), signed(1));
+
+        let serialized = to_vec(&signed(-42)).unwrap();
+        assert_eq!(serialized, b"\"-42\"");
+        let deserialized: SignedDecimal = from_slice(&serialized).unwrap();
+        assert_eq!(deserialized, signed(-42));
+    }
+}